@@ -0,0 +1,72 @@
+use actix_token_middleware::{keys::StaticKeys, Jwt};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures::executor::block_on;
+use jsonwebtoken as jwt;
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Jwt checking tokens with the fixture keys
+fn fixture_jwt(claims: Vec<(String, Value)>) -> Jwt {
+	let keys = StaticKeys::from_json(include_str!("../tests/fixtures/jwks.json")).unwrap();
+	block_on(Jwt::from_store(keys, claims)).unwrap()
+}
+
+/// Sign a token valid for an hour with the given fixture key
+fn mint(alg: jwt::Algorithm, kid: &str, key: &jwt::EncodingKey) -> String {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap()
+		.as_secs();
+	let claims = json!({
+		"iss": "example.com",
+		"ref_protected": "true",
+		"project_path": "alpine/staticserve",
+		"iat": now,
+		"exp": now + 3600,
+	});
+	let mut header = jwt::Header::new(alg);
+	header.kid = Some(kid.to_owned());
+	jwt::encode(&header, &claims, key).unwrap()
+}
+
+fn validation(c: &mut Criterion) {
+	let jwt = fixture_jwt(vec![
+		("iss".to_owned(), "example.com".into()),
+		("ref_protected".to_owned(), "true".into()),
+		("project_path".to_owned(), "alpine/staticserve".into()),
+	]);
+	let rsa = mint(
+		jwt::Algorithm::RS256,
+		"test-key",
+		&jwt::EncodingKey::from_rsa_pem(include_bytes!("../tests/fixtures/key.pem")).unwrap(),
+	);
+	let ec = mint(
+		jwt::Algorithm::ES256,
+		"test-ec-key",
+		&jwt::EncodingKey::from_ec_pem(include_bytes!("../tests/fixtures/ec.pem")).unwrap(),
+	);
+	let keys = jwt.keys();
+	let tokendata = jwt.check_jwt(&rsa).unwrap();
+
+	c.bench_function("decode header", |b| {
+		b.iter(|| jwt::decode_header(black_box(&rsa)).unwrap())
+	});
+	c.bench_function("kid lookup", |b| {
+		b.iter(|| keys.jwk(black_box("test-key")).unwrap())
+	});
+	c.bench_function("verify RS256", |b| {
+		b.iter(|| jwt.check_jwt(black_box(&rsa)).unwrap())
+	});
+	c.bench_function("verify ES256", |b| {
+		b.iter(|| jwt.check_jwt(black_box(&ec)).unwrap())
+	});
+	c.bench_function("claim matching", |b| {
+		b.iter(|| jwt.check_claims(black_box(&tokendata)).unwrap())
+	});
+	c.bench_function("validate RS256", |b| {
+		b.iter(|| jwt.validate_jwt(black_box(&rsa)).unwrap())
+	});
+}
+
+criterion_group!(benches, validation);
+criterion_main!(benches);
@@ -0,0 +1,221 @@
+use crate::result::Result;
+
+use futures_util::future::LocalBoxFuture;
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+/// Store of short lived values shared by the caches of the middlewares (userinfo...), so that
+/// replicas can share their state through an external store instead of each keeping its own
+pub trait Cache {
+	/// Return the value of the key unless it expired
+	fn get<'a>(&'a self, key: &'a str) -> LocalBoxFuture<'a, Result<Option<Vec<u8>>>>;
+
+	/// Store the value of the key for the given duration
+	fn set<'a>(
+		&'a self,
+		key: &'a str,
+		value: Vec<u8>,
+		ttl: Duration,
+	) -> LocalBoxFuture<'a, Result<()>>;
+
+	/// Store the value of the key for the given duration unless the key is already set, and
+	/// return true if it was stored. Atomic, to detect the first use of a value
+	fn set_new<'a>(
+		&'a self,
+		key: &'a str,
+		value: Vec<u8>,
+		ttl: Duration,
+	) -> LocalBoxFuture<'a, Result<bool>>;
+}
+
+/// In-memory cache, private to the process
+#[derive(Debug)]
+pub struct MemoryCache {
+	capacity: usize,
+	entries: Mutex<HashMap<String, (Vec<u8>, Instant)>>,
+}
+
+impl MemoryCache {
+	/// Keep at most the given number of entries, dropping the ones expiring first when full
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			entries: Mutex::default(),
+		}
+	}
+
+	/// Insert an entry, making room for it if necessary
+	fn insert(
+		entries: &mut HashMap<String, (Vec<u8>, Instant)>,
+		capacity: usize,
+		key: &str,
+		value: Vec<u8>,
+		ttl: Duration,
+	) {
+		if entries.len() >= capacity && !entries.contains_key(key) {
+			let now = Instant::now();
+			entries.retain(|_, (_, expires)| *expires > now);
+			if entries.len() >= capacity {
+				let first = entries
+					.iter()
+					.min_by_key(|(_, (_, expires))| *expires)
+					.map(|(key, _)| key.clone());
+				if let Some(first) = first {
+					entries.remove(&first);
+				}
+			}
+		}
+		entries.insert(key.to_owned(), (value, Instant::now() + ttl));
+	}
+}
+
+impl Default for MemoryCache {
+	fn default() -> Self {
+		Self::new(10_000)
+	}
+}
+
+impl Cache for MemoryCache {
+	fn get<'a>(&'a self, key: &'a str) -> LocalBoxFuture<'a, Result<Option<Vec<u8>>>> {
+		let entries = self.entries.lock().unwrap();
+		let value = entries
+			.get(key)
+			.filter(|(_, expires)| *expires > Instant::now())
+			.map(|(value, _)| value.clone());
+		Box::pin(async move { Ok(value) })
+	}
+
+	fn set<'a>(
+		&'a self,
+		key: &'a str,
+		value: Vec<u8>,
+		ttl: Duration,
+	) -> LocalBoxFuture<'a, Result<()>> {
+		let mut entries = self.entries.lock().unwrap();
+		Self::insert(&mut entries, self.capacity, key, value, ttl);
+		Box::pin(async move { Ok(()) })
+	}
+
+	fn set_new<'a>(
+		&'a self,
+		key: &'a str,
+		value: Vec<u8>,
+		ttl: Duration,
+	) -> LocalBoxFuture<'a, Result<bool>> {
+		let mut entries = self.entries.lock().unwrap();
+		let set = match entries.get(key) {
+			Some((_, expires)) if *expires > Instant::now() => false,
+			_ => {
+				Self::insert(&mut entries, self.capacity, key, value, ttl);
+				true
+			}
+		};
+		Box::pin(async move { Ok(set) })
+	}
+}
+
+/// Cache stored in Redis, shared by all the replicas
+#[cfg(feature = "redis")]
+#[derive(Clone)]
+pub struct RedisCache {
+	conn: redis::aio::ConnectionManager,
+	prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCache {
+	/// Connect to the Redis server at the given url (eg. `redis://cache:6379/0`)
+	pub async fn connect(url: &str) -> Result<Self> {
+		let client = redis::Client::open(url).map_err(cache_error)?;
+		let conn = redis::aio::ConnectionManager::new(client)
+			.await
+			.map_err(cache_error)?;
+		Ok(Self {
+			conn,
+			prefix: "auth:".to_owned(),
+		})
+	}
+
+	/// Prefix of the keys (`auth:` by default), to share a server between applications
+	pub fn prefix(mut self, prefix: &str) -> Self {
+		self.prefix = prefix.to_owned();
+		self
+	}
+
+	/// Build the SET command of the key
+	fn set_cmd(&self, key: &str, value: Vec<u8>, ttl: Duration) -> redis::Cmd {
+		let mut cmd = redis::cmd("SET");
+		cmd.arg(format!("{}{}", self.prefix, key))
+			.arg(value)
+			.arg("PX")
+			.arg(ttl.as_millis().max(1) as u64);
+		cmd
+	}
+}
+
+#[cfg(feature = "redis")]
+impl Cache for RedisCache {
+	fn get<'a>(&'a self, key: &'a str) -> LocalBoxFuture<'a, Result<Option<Vec<u8>>>> {
+		let mut conn = self.conn.clone();
+		Box::pin(async move {
+			redis::cmd("GET")
+				.arg(format!("{}{}", self.prefix, key))
+				.query_async(&mut conn)
+				.await
+				.map_err(cache_error)
+		})
+	}
+
+	fn set<'a>(
+		&'a self,
+		key: &'a str,
+		value: Vec<u8>,
+		ttl: Duration,
+	) -> LocalBoxFuture<'a, Result<()>> {
+		let mut conn = self.conn.clone();
+		let cmd = self.set_cmd(key, value, ttl);
+		Box::pin(async move { cmd.query_async(&mut conn).await.map_err(cache_error) })
+	}
+
+	fn set_new<'a>(
+		&'a self,
+		key: &'a str,
+		value: Vec<u8>,
+		ttl: Duration,
+	) -> LocalBoxFuture<'a, Result<bool>> {
+		let mut conn = self.conn.clone();
+		let mut cmd = self.set_cmd(key, value, ttl);
+		cmd.arg("NX");
+		Box::pin(async move {
+			cmd.query_async::<_, Option<String>>(&mut conn)
+				.await
+				.map(|res| res.is_some())
+				.map_err(cache_error)
+		})
+	}
+}
+
+#[cfg(feature = "redis")]
+fn cache_error(e: redis::RedisError) -> crate::result::Error {
+	crate::result::Error::Cache(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	#[test]
+	fn memory_cache() {
+		let cache = MemoryCache::new(1);
+		let ttl = Duration::from_secs(60);
+		assert!(block_on(cache.set_new("a", b"1".to_vec(), ttl)).unwrap());
+		assert!(!block_on(cache.set_new("a", b"2".to_vec(), ttl)).unwrap());
+		block_on(cache.set("b", b"3".to_vec(), ttl)).unwrap();
+		assert_eq!(block_on(cache.get("a")).unwrap(), None);
+		assert_eq!(block_on(cache.get("b")).unwrap(), Some(b"3".to_vec()));
+	}
+}
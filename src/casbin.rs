@@ -0,0 +1,62 @@
+use crate::result::{Error, Result};
+
+use ::casbin::{CoreApi, Enforcer};
+use actix_web::dev::ServiceRequest;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Casbin enforcement of the validated requests: the subject (`sub` claim by default), the
+/// path and the method of the request are checked against the model and the policy of the
+/// enforcer, and denied requests are rejected with 403
+#[derive(Clone)]
+pub struct Casbin {
+	enforcer: Arc<RwLock<Enforcer>>,
+	subject: String,
+}
+
+impl Casbin {
+	/// Enforce the policy of the given enforcer
+	pub fn new(enforcer: Enforcer) -> Self {
+		Self::shared(Arc::new(RwLock::new(enforcer)))
+	}
+
+	/// Enforce the policy of an enforcer shared with the application, which can update it at
+	/// runtime
+	pub fn shared(enforcer: Arc<RwLock<Enforcer>>) -> Self {
+		Self {
+			enforcer,
+			subject: "sub".to_owned(),
+		}
+	}
+
+	/// Take the subject from the given claim (eg. `preferred_username`)
+	pub fn subject(mut self, claim: &str) -> Self {
+		self.subject = claim.to_owned();
+		self
+	}
+
+	/// Check that the subject of the claims is allowed to send the request
+	pub(crate) async fn check(&self, req: &ServiceRequest, claims: &Value) -> Result<()> {
+		let sub = match claims.get(&self.subject) {
+			Some(Value::String(sub)) => sub.clone(),
+			Some(sub) => sub.to_string(),
+			None => return Err(Error::ClaimNotFound(self.subject.clone())),
+		};
+		let (path, method) = (req.path(), req.method().as_str());
+		let allowed = self
+			.enforcer
+			.read()
+			.await
+			.enforce((sub.as_str(), path, method))
+			.unwrap_or_else(|e| {
+				tracing::warn!("Failed to enforce the casbin policy: {}", e);
+				false
+			});
+		if allowed {
+			Ok(())
+		} else {
+			Err(Error::Forbidden(format!("{} {} {}", sub, method, path)))
+		}
+	}
+}
@@ -0,0 +1,105 @@
+use crate::result::{Error, Result};
+
+use actix_web::dev::ServiceRequest;
+use cedar_policy::{
+	Authorizer, Context, Decision, Entities, EntityId, EntityTypeName, EntityUid, PolicySet,
+	Request,
+};
+use serde_json::{json, Value};
+use std::{str::FromStr, sync::Arc};
+
+/// Cedar evaluation of the validated requests: the principal (`User::"<sub>"` by default) is
+/// checked against the action `Action::"<method>"` on the resource `Resource::"<path>"`, the
+/// claims being available to the policies as `context.claims`. Denied requests are rejected
+/// with 403
+#[derive(Clone)]
+pub struct Cedar {
+	policies: Arc<PolicySet>,
+	principal: EntityTypeName,
+	subject: String,
+}
+
+/// Parse an entity type
+fn entity_type(kind: &str) -> Result<EntityTypeName> {
+	EntityTypeName::from_str(kind)
+		.map_err(|e| Error::Config(format!("invalid cedar entity type {}: {}", kind, e)))
+}
+
+/// Build an entity uid of one of the built-in types
+fn uid(kind: &str, id: &str) -> EntityUid {
+	let kind = entity_type(kind).expect("built-in types are valid");
+	EntityUid::from_type_name_and_id(kind, EntityId::new(id))
+}
+
+impl Cedar {
+	/// Evaluate the given policies
+	pub fn new(policies: &str) -> Result<Self> {
+		let policies = PolicySet::from_str(policies)
+			.map_err(|e| Error::Config(format!("invalid cedar policies: {}", e)))?;
+		Ok(Self {
+			policies: Arc::new(policies),
+			principal: entity_type("User")?,
+			subject: "sub".to_owned(),
+		})
+	}
+
+	/// Build the principal from the given entity type and claim (eg. `Service` and `client_id`)
+	pub fn principal(mut self, kind: &str, claim: &str) -> Result<Self> {
+		self.principal = entity_type(kind)?;
+		self.subject = claim.to_owned();
+		Ok(self)
+	}
+
+	/// Check that the principal of the claims is allowed to send the request
+	pub(crate) fn check(&self, req: &ServiceRequest, claims: &Value) -> Result<()> {
+		let sub = match claims.get(&self.subject) {
+			Some(Value::String(sub)) => sub.clone(),
+			Some(sub) => sub.to_string(),
+			None => return Err(Error::ClaimNotFound(self.subject.clone())),
+		};
+		let (path, method) = (req.path(), req.method().as_str());
+		let denied = || Error::Forbidden(format!("{} {} {}", sub, method, path));
+		let principal =
+			EntityUid::from_type_name_and_id(self.principal.clone(), EntityId::new(&sub));
+		let (action, resource) = (uid("Action", method), uid("Resource", path));
+		let request = Context::from_json_value(json!({ "claims": claims }), None)
+			.map_err(|e| e.to_string())
+			.and_then(|context| {
+				Request::new(Some(principal), Some(action), Some(resource), context, None)
+					.map_err(|e| e.to_string())
+			})
+			.map_err(|e| {
+				tracing::warn!("Failed to build the cedar request: {}", e);
+				denied()
+			})?;
+		let response =
+			Authorizer::new().is_authorized(&request, &self.policies, &Entities::empty());
+		match response.decision() {
+			Decision::Allow => Ok(()),
+			Decision::Deny => Err(denied()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+
+	#[test]
+	fn evaluate_policies() {
+		let cedar = Cedar::new(
+			r#"permit(principal == User::"alice", action == Action::"GET", resource)
+			when { context.claims.admin };"#,
+		)
+		.unwrap();
+		let req = TestRequest::with_uri("/api").to_srv_request();
+		assert!(cedar
+			.check(&req, &json!({ "sub": "alice", "admin": true }))
+			.is_ok());
+		assert!(matches!(
+			cedar.check(&req, &json!({ "sub": "bob", "admin": true })),
+			Err(Error::Forbidden(_))
+		));
+	}
+}
@@ -0,0 +1,30 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the current time used to check the exp and nbf claims, so that tests can freeze it
+pub trait Clock {
+	/// Seconds since the epoch
+	fn now(&self) -> u64;
+}
+
+/// Clock of the system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> u64 {
+		SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or_default()
+	}
+}
+
+/// Clock frozen at the given number of seconds since the epoch
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+	fn now(&self) -> u64 {
+		self.0
+	}
+}
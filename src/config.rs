@@ -0,0 +1,286 @@
+use crate::{
+	data::ClaimOverride,
+	keys::KeyPolicy,
+	middleware::{
+		jwtauth::{RefreshPolicy, TokenSource},
+		reject::{ErrorFormat, ErrorTemplate},
+	},
+	result::{Error, Result},
+};
+
+use actix_web::http::header::HeaderName;
+use serde::Deserialize;
+use serde_json::Value;
+use serde_vecmap::vecmap;
+use std::{collections::HashMap, env, str::FromStr, time::Duration};
+
+/// Read an optional environment variable
+pub(crate) fn var(name: &str) -> Result<Option<String>> {
+	match env::var(name) {
+		Ok(value) => Ok(Some(value)),
+		Err(env::VarError::NotPresent) => Ok(None),
+		Err(env::VarError::NotUnicode(_)) => {
+			Err(Error::Config(format!("{} is not valid unicode", name)))
+		}
+	}
+}
+
+/// Read a mandatory environment variable
+pub(crate) fn required_var(name: &str) -> Result<String> {
+	var(name)?.ok_or_else(|| Error::Config(format!("{} must be set", name)))
+}
+
+/// Parse `key=value` pairs separated by commas (eg. `iss=gitlab.com,ref_protected=true`)
+pub(crate) fn parse_claims(name: &str, value: &str) -> Result<Vec<(String, String)>> {
+	value
+		.split(',')
+		.map(str::trim)
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| {
+			pair.split_once('=')
+				.map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+				.ok_or_else(|| {
+					Error::Config(format!(
+						"{} must be a list of key=value, found {}",
+						name, pair
+					))
+				})
+		})
+		.collect()
+}
+
+/// Collect the problems of a jwks endpoint url
+pub(crate) fn check_jwks(url: &str, allow_insecure: bool, problems: &mut Vec<String>) {
+	if url.is_empty() {
+		problems.push("jwks endpoint is empty".to_owned());
+	} else if !allow_insecure && !url.starts_with("https://") {
+		problems.push(format!("jwks endpoint {} is not https", url));
+	}
+}
+
+/// Collect the problems of claim rules
+pub(crate) fn check_claims(claims: &[(String, Value)], problems: &mut Vec<String>) {
+	for (key, value) in claims {
+		if key.trim().is_empty() {
+			problems.push("claim with an empty name".to_owned());
+		}
+		if value.is_null() {
+			problems.push(format!("claim {} has no expected value", key));
+		}
+	}
+}
+
+/// Return all the collected problems at once
+pub(crate) fn problems(problems: Vec<String>) -> Result<()> {
+	if problems.is_empty() {
+		Ok(())
+	} else {
+		Err(Error::Invalid(problems))
+	}
+}
+
+/// Where the token is read from in the configuration
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceConfig {
+	/// `Bearer` credentials of the given header
+	Header(String),
+	/// Raw value of the given cookie
+	Cookie(String),
+}
+
+impl Default for SourceConfig {
+	fn default() -> Self {
+		Self::Header("authorization".to_owned())
+	}
+}
+
+impl SourceConfig {
+	/// Convert to the source used by the middleware
+	pub fn token_source(&self) -> Result<TokenSource> {
+		match self {
+			Self::Header(name) => HeaderName::from_str(name)
+				.map(TokenSource::Header)
+				.map_err(|_| Error::Config(format!("invalid header name {}", name))),
+			Self::Cookie(name) => Ok(TokenSource::Cookie(name.to_owned())),
+		}
+	}
+}
+
+/// Configuration of a JwtAuth in a single block deserializable from any serde format
+/// (TOML, YAML, JSON...)
+///
+/// ```yaml
+/// jwks: https://gitlab.com/-/jwks
+/// claims:
+///   iss: gitlab.com
+/// leeway: 30
+/// source:
+///   cookie: session
+/// refresh: 3600
+/// error_format: problem
+/// error_template:
+///   body: '{"code": "{reason}", "message": "{message}", "trace": "{request_id}"}'
+///   content_type: application/json
+/// profiles:
+///   deploy:
+///     ref_protected: "true"
+///   read-only: {}
+/// issuer_claims:
+///   https://gitlab.com:
+///     project_path: group/project
+/// ```
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct AuthConfig {
+	/// jwks endpoint
+	pub jwks: String,
+	/// claims to validate the JWT tokens against
+	#[serde(default)]
+	#[serde(with = "vecmap")]
+	pub claims: Vec<(String, Value)>,
+	/// leeway in seconds when checking exp and nbf
+	#[serde(default)]
+	pub leeway: u64,
+	/// tolerated skew in seconds of the iat claim (the leeway by default)
+	#[serde(default)]
+	pub iat_skew: Option<u64>,
+	/// maximum length in bytes of the tokens (8 KiB by default)
+	#[serde(default)]
+	pub max_token_len: Option<usize>,
+	/// where the token is read from
+	#[serde(default)]
+	pub source: SourceConfig,
+	/// interval in seconds between two reloads of the keys (never by default)
+	#[serde(default)]
+	pub refresh: Option<u64>,
+	/// format of the rejection body
+	#[serde(default)]
+	pub error_format: ErrorFormat,
+	/// template of the rejection body replacing the error format
+	#[serde(default)]
+	pub error_template: Option<ErrorTemplate>,
+	/// name of the protected realm
+	#[serde(default)]
+	pub realm: Option<String>,
+	/// include the precise failure in the rejections (development only)
+	#[serde(default)]
+	pub debug: bool,
+	/// send the remaining lifetime of the token in a `X-Token-Expires-In` response header
+	#[serde(default)]
+	pub expires_in_header: bool,
+	/// allow loading the keys over plain http (local development only)
+	#[serde(default)]
+	pub allow_insecure_jwks: bool,
+	/// kids or RFC 7638 thumbprints of the only keys accepted (any key if empty)
+	#[serde(default)]
+	pub pins: Vec<String>,
+	/// minimum strength of the keys
+	#[serde(default)]
+	pub key_policy: KeyPolicy,
+	/// accept tokens without kid by trying all the keys of their algorithm
+	#[serde(default)]
+	pub allow_missing_kid: bool,
+	/// expected issuer with a `{tenantid}` placeholder replaced by the tid claim
+	#[serde(default)]
+	pub issuer_template: Option<String>,
+	/// match the kids regardless of their case
+	#[serde(default)]
+	pub ignore_kid_case: bool,
+	/// named sets of claims required on top of the base ones
+	#[serde(default)]
+	pub profiles: HashMap<String, ClaimOverride>,
+	/// claims required on top of the base ones from the tokens of the given issuers
+	#[serde(default)]
+	pub issuer_claims: HashMap<String, ClaimOverride>,
+}
+
+impl AuthConfig {
+	/// Refresh policy of the keys
+	pub fn refresh_policy(&self) -> RefreshPolicy {
+		match self.refresh {
+			Some(secs) => RefreshPolicy::Interval(Duration::from_secs(secs)),
+			None => RefreshPolicy::Never,
+		}
+	}
+
+	/// Check the configuration for contradictions before serving traffic and report all the
+	/// problems at once
+	pub fn validate(&self) -> Result<()> {
+		let mut problems = Vec::new();
+		check_jwks(&self.jwks, self.allow_insecure_jwks, &mut problems);
+		check_claims(&self.claims, &mut problems);
+		for (name, profile) in &self.profiles {
+			let mut profile_problems = Vec::new();
+			check_claims(profile.claims(), &mut profile_problems);
+			problems.extend(
+				profile_problems
+					.into_iter()
+					.map(|problem| format!("profile {}: {}", name, problem)),
+			);
+		}
+		for (iss, claims) in &self.issuer_claims {
+			let mut issuer_problems = Vec::new();
+			check_claims(claims.claims(), &mut issuer_problems);
+			problems.extend(
+				issuer_problems
+					.into_iter()
+					.map(|problem| format!("issuer {}: {}", iss, problem)),
+			);
+		}
+		if let Err(e) = self.source.token_source() {
+			problems.push(e.to_string());
+		}
+		if self.refresh == Some(0) {
+			problems.push("refresh interval must be positive".to_owned());
+		}
+		self::problems(problems)
+	}
+
+	/// Return the claims of the named profile, to be registered with `app_data` on a scope
+	pub fn profile(&self, name: &str) -> Result<ClaimOverride> {
+		self.profiles
+			.get(name)
+			.cloned()
+			.ok_or_else(|| Error::Config(format!("unknown claim profile {}", name)))
+	}
+
+	/// Return the configuration with the claims of the named profile merged in the base ones,
+	/// to build a JwtAuth protecting a group of routes differently
+	pub fn with_profile(&self, name: &str) -> Result<Self> {
+		let mut config = self.clone();
+		config.claims = self.profile(name)?.merge(&self.claims);
+		Ok(config)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn claims_from_env() {
+		let claims = parse_claims("JWT_CLAIMS", "iss=gitlab.com, ref_protected=true").unwrap();
+		assert_eq!(
+			claims,
+			vec![
+				("iss".to_owned(), "gitlab.com".to_owned()),
+				("ref_protected".to_owned(), "true".to_owned())
+			]
+		);
+		assert!(parse_claims("JWT_CLAIMS", "iss").is_err());
+	}
+
+	#[test]
+	fn report_all_problems() {
+		let config = AuthConfig {
+			jwks: "http://gitlab.com/-/jwks".to_owned(),
+			claims: vec![("".to_owned(), "gitlab.com".into())],
+			source: SourceConfig::Header("bad header".to_owned()),
+			..AuthConfig::default()
+		};
+		match config.validate() {
+			Err(Error::Invalid(problems)) => assert_eq!(problems.len(), 3),
+			res => panic!("unexpected {:?}", res),
+		}
+	}
+}
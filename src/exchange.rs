@@ -0,0 +1,163 @@
+use crate::result::{Error, Result};
+
+use actix_web::{
+	dev::Payload,
+	error::{ErrorBadGateway, ErrorInternalServerError, ErrorUnauthorized},
+	web, FromRequest, HttpMessage, HttpRequest,
+};
+use awc::Client;
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Token validated by JwtAuth, kept in the request extensions for the exchange
+#[derive(Clone)]
+pub(crate) struct SubjectToken(pub(crate) String);
+
+/// Token returned by the authorization server
+#[derive(Deserialize, Clone, Debug)]
+pub struct ExchangedToken {
+	/// the downstream token
+	pub access_token: String,
+	/// type of the downstream token (an access token unless requested otherwise)
+	#[serde(default)]
+	pub issued_token_type: Option<String>,
+	/// how the token is presented (`Bearer` usually)
+	pub token_type: String,
+	/// lifetime in seconds of the token
+	#[serde(default)]
+	pub expires_in: Option<u64>,
+	/// scope of the token when it differs from the requested one
+	#[serde(default)]
+	pub scope: Option<String>,
+}
+
+/// RFC 8693 token exchange client trading the token validated by JwtAuth for a downstream
+/// token to call other services on behalf of the same subject
+#[derive(Clone)]
+pub struct TokenExchange {
+	endpoint: String,
+	client_id: String,
+	client_secret: Option<Zeroizing<String>>,
+	audience: Option<String>,
+	scope: Option<String>,
+	resource: Option<String>,
+}
+
+impl TokenExchange {
+	/// Exchange the tokens at the given token endpoint as the given (public) client
+	pub fn new(token_endpoint: &str, client_id: &str) -> Self {
+		Self {
+			endpoint: token_endpoint.to_owned(),
+			client_id: client_id.to_owned(),
+			client_secret: None,
+			audience: None,
+			scope: None,
+			resource: None,
+		}
+	}
+
+	/// Authenticate the client with the given secret (`client_secret_basic`)
+	pub fn client_secret(mut self, secret: &str) -> Self {
+		self.client_secret = Some(Zeroizing::new(secret.to_owned()));
+		self
+	}
+
+	/// Request a token for the given audience (logical name of the downstream service)
+	pub fn audience(mut self, audience: &str) -> Self {
+		self.audience = Some(audience.to_owned());
+		self
+	}
+
+	/// Request a token with the given space separated scopes
+	pub fn scope(mut self, scope: &str) -> Self {
+		self.scope = Some(scope.to_owned());
+		self
+	}
+
+	/// Request a token for the given resource (url of the downstream service)
+	pub fn resource(mut self, resource: &str) -> Self {
+		self.resource = Some(resource.to_owned());
+		self
+	}
+
+	/// Exchange the given access token
+	pub async fn exchange(&self, subject_token: &str) -> Result<ExchangedToken> {
+		let mut form = vec![
+			("grant_type", GRANT_TYPE),
+			("subject_token", subject_token),
+			("subject_token_type", ACCESS_TOKEN),
+		];
+		let optional = [
+			("audience", &self.audience),
+			("scope", &self.scope),
+			("resource", &self.resource),
+		];
+		form.extend(
+			optional
+				.into_iter()
+				.filter_map(|(key, value)| value.as_deref().map(|value| (key, value))),
+		);
+		let mut req = Client::default().post(&self.endpoint);
+		match &self.client_secret {
+			Some(secret) => req = req.basic_auth(&self.client_id, secret.as_str()),
+			None => form.push(("client_id", self.client_id.as_str())),
+		}
+		let mut res = req
+			.send_form(&form)
+			.await
+			.map_err(|e| Error::Exchange(e.to_string()))?;
+		if !res.status().is_success() {
+			let body = res.body().await.unwrap_or_default();
+			return Err(Error::Exchange(format!(
+				"{} returned {}: {}",
+				self.endpoint,
+				res.status(),
+				String::from_utf8_lossy(&body)
+			)));
+		}
+		res.json::<ExchangedToken>()
+			.await
+			.map_err(|e| Error::Exchange(e.to_string()))
+	}
+
+	/// Exchange the token validated by JwtAuth for the request
+	pub async fn exchange_request(&self, req: &HttpRequest) -> Result<ExchangedToken> {
+		let token = req
+			.extensions()
+			.get::<SubjectToken>()
+			.map(|token| token.0.clone())
+			.ok_or(Error::NoBearer)?;
+		self.exchange(&token).await
+	}
+}
+
+/// Downstream token obtained by exchanging the token validated by JwtAuth with the
+/// `web::Data<TokenExchange>` of the application. Requests without validated token are
+/// rejected with 401, failed exchanges with 502
+pub struct Exchanged(pub ExchangedToken);
+
+impl FromRequest for Exchanged {
+	type Error = actix_web::Error;
+	type Future = LocalBoxFuture<'static, std::result::Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+		let req = req.clone();
+		Box::pin(async move {
+			let exchange = req
+				.app_data::<web::Data<TokenExchange>>()
+				.ok_or_else(|| ErrorInternalServerError("token exchange is not configured"))?;
+			match exchange.exchange_request(&req).await {
+				Ok(token) => Ok(Exchanged(token)),
+				Err(Error::NoBearer) => Err(ErrorUnauthorized("not authorized")),
+				Err(e) => {
+					tracing::warn!("{}", e);
+					Err(ErrorBadGateway("token exchange failed"))
+				}
+			}
+		})
+	}
+}
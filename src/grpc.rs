@@ -0,0 +1,81 @@
+use crate::{
+	data::{Claims, Jwt},
+	middleware::reject,
+	result::Error as AuthError,
+};
+
+use actix_web::http::StatusCode;
+use serde_json::Value;
+use tonic::{service::Interceptor, Request, Status};
+
+/// `tonic` interceptor validating the bearer token of the `authorization` metadata. Clones of
+/// a Jwt share their keys, so a service serving both actix and tonic can validate with the Jwt
+/// of its JwtAuth and load the keys once
+///
+/// ```ignore
+/// let interceptor = JwtInterceptor::new(auth.jwt().clone());
+/// Server::builder().add_service(GreeterServer::with_interceptor(greeter, interceptor))
+/// ```
+#[derive(Clone)]
+pub struct JwtInterceptor {
+	jwt: Jwt,
+}
+
+impl JwtInterceptor {
+	/// Validate the tokens with the given Jwt
+	pub fn new(jwt: Jwt) -> Self {
+		Self { jwt }
+	}
+
+	/// Validate the token of the request metadata and return its claims
+	fn validate<T>(&self, req: &Request<T>) -> Result<Value, AuthError> {
+		let token = req
+			.metadata()
+			.get("authorization")
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.split_once(' '))
+			.filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+			.map(|(_, token)| token.trim())
+			.ok_or(AuthError::NoBearer)?;
+		Ok(self.jwt.validate_jwt(token)?.claims)
+	}
+}
+
+/// Map a rejection to the gRPC status matching its HTTP status
+fn status(e: &AuthError) -> Status {
+	let message = e.to_string();
+	match reject::status(e) {
+		StatusCode::FORBIDDEN => Status::permission_denied(message),
+		StatusCode::TOO_MANY_REQUESTS => Status::resource_exhausted(message),
+		StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+		_ => Status::unauthenticated(message),
+	}
+}
+
+impl Interceptor for JwtInterceptor {
+	fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
+		match self.validate(&req) {
+			Ok(claims) => {
+				req.extensions_mut().insert(Claims(claims));
+				Ok(req)
+			}
+			Err(e) => {
+				tracing::info!("gRPC request rejected: {}", e);
+				Err(status(&e))
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tonic::Code;
+
+	#[test]
+	fn reject_without_token() {
+		let mut interceptor = JwtInterceptor::new(Jwt::from_keys(Vec::new(), Vec::new()));
+		let status = interceptor.call(Request::new(())).unwrap_err();
+		assert_eq!(status.code(), Code::Unauthenticated);
+	}
+}
@@ -0,0 +1,132 @@
+use crate::{
+	data::Jwt,
+	middleware::{
+		identity::IdentityHeaders,
+		jwtauth::TokenSource,
+		reject::{self, ErrorFormat},
+	},
+	result::Error as AuthError,
+};
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde_json::json;
+use std::{sync::Arc, time::UNIX_EPOCH};
+
+/// Standalone authentication endpoint implementing the traefik `ForwardAuth` / nginx
+/// `auth_request` contract: 200 with identity headers when the token is valid, 401 otherwise
+#[derive(Clone)]
+pub struct ForwardAuth {
+	jwt: Arc<Jwt>,
+	source: TokenSource,
+	identity: IdentityHeaders,
+	error_format: ErrorFormat,
+	realm: Option<String>,
+}
+
+impl ForwardAuth {
+	/// Validate tokens with the given Jwt and answer with the gitlab identity headers
+	pub fn new(jwt: Jwt) -> Self {
+		Self {
+			jwt: Arc::new(jwt),
+			source: TokenSource::default(),
+			identity: IdentityHeaders::gitlab(),
+			error_format: ErrorFormat::default(),
+			realm: None,
+		}
+	}
+
+	/// Read the token from the given source (`Authorization: Bearer` by default)
+	pub fn source(mut self, source: TokenSource) -> Self {
+		self.source = source;
+		self
+	}
+
+	/// Set the identity headers returned on success
+	pub fn identity_headers(mut self, identity: IdentityHeaders) -> Self {
+		self.identity = identity;
+		self
+	}
+
+	/// Set the format of the rejection body
+	pub fn error_format(mut self, format: ErrorFormat) -> Self {
+		self.error_format = format;
+		self
+	}
+
+	/// Name the protected realm in challenges and error bodies
+	pub fn realm(mut self, realm: &str) -> Self {
+		self.realm = Some(realm.to_owned());
+		self
+	}
+
+	/// Validate the token of the request and build the answer for the proxy
+	pub fn respond(&self, req: &HttpRequest) -> HttpResponse {
+		// the first valid token is accepted, the error of the first one is reported otherwise
+		let mut res = Err(AuthError::NoBearer);
+		for (i, jwt) in self.source.tokens(req).iter().enumerate() {
+			match self.jwt.validate_jwt(jwt) {
+				Ok(tokendata) => {
+					res = Ok(tokendata);
+					break;
+				}
+				Err(e) if i == 0 => res = Err(e),
+				Err(_) => (),
+			}
+		}
+		match res {
+			Ok(tokendata) => {
+				let mut builder = HttpResponse::Ok();
+				for (name, value) in self.identity.values(&tokendata.claims) {
+					builder.insert_header((name.clone(), value));
+				}
+				builder.finish()
+			}
+			Err(e) => reject::response(&e, self.error_format, self.realm.as_deref()),
+		}
+	}
+}
+
+/// Forward auth handler expecting a `Data<ForwardAuth>` in the app data
+///
+/// ```ignore
+/// App::new()
+///     .app_data(web::Data::new(ForwardAuth::new(jwt)))
+///     .route("/auth", web::to(forward_auth))
+/// ```
+pub async fn forward_auth(req: HttpRequest, auth: web::Data<ForwardAuth>) -> HttpResponse {
+	auth.respond(&req)
+}
+
+/// Health of the validation for readiness probes, expecting a `Data<Jwt>` in the app data:
+/// the number of loaded keys, the outcome of the last refresh and a digest of the
+/// configuration. Answers 503 when no key is loaded, as no token can be validated
+///
+/// ```ignore
+/// App::new()
+///     .app_data(web::Data::new(jwt))
+///     .route("/authz/health", web::get().to(health))
+/// ```
+pub async fn health(jwt: web::Data<Jwt>) -> HttpResponse {
+	let keys = jwt.key_count();
+	let last_refresh = jwt.last_refresh().map(|(at, error)| {
+		json!({
+			"at": at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default(),
+			"ok": error.is_none(),
+			"error": error,
+		})
+	});
+	let body = json!({
+		"status": if keys > 0 { "ok" } else { "unavailable" },
+		"keys": keys,
+		"last_refresh": last_refresh,
+		"config": jwt.digest(),
+	});
+	let mut builder = if keys > 0 {
+		HttpResponse::Ok()
+	} else {
+		HttpResponse::ServiceUnavailable()
+	};
+	builder
+		.content_type("application/json")
+		.body(body.to_string())
+}
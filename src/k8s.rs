@@ -0,0 +1,126 @@
+use crate::{
+	data::{Jwks, Jwt},
+	keys::KeyStore,
+	pem::pem_key,
+	result::{Error, Result},
+};
+
+use actix_web::rt;
+use futures_util::future::LocalBoxFuture;
+use jsonwebkey as jwk;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+/// Keys read from a directory where a Kubernetes Secret or ConfigMap is mounted: JWKS or JWK
+/// documents (`*.json`) and PEM public keys (`*.pem`, the kid being the file name)
+#[derive(Clone, Debug)]
+pub struct DirectoryKeys {
+	dir: PathBuf,
+}
+
+impl DirectoryKeys {
+	/// Read the keys of the given directory
+	pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+		Self {
+			dir: dir.as_ref().to_owned(),
+		}
+	}
+
+	/// Read all the keys of the directory
+	pub fn read(&self) -> Result<Vec<jwk::JsonWebKey>> {
+		let error = |e: std::io::Error| Error::Refresh(format!("{}: {}", self.dir.display(), e));
+		let mut keys = Vec::new();
+		for entry in fs::read_dir(&self.dir).map_err(error)? {
+			let path = entry.map_err(error)?.path();
+			let name = path
+				.file_name()
+				.and_then(|name| name.to_str())
+				.unwrap_or_default();
+			// skip the kubelet internal entries (..data and the timestamped directories)
+			if name.starts_with('.') || !path.is_file() {
+				continue;
+			}
+			match path.extension().and_then(|ext| ext.to_str()) {
+				Some("json") => {
+					let content = fs::read_to_string(&path).map_err(error)?;
+					match Jwks::parse(&content) {
+						Ok(jwks) => keys.extend(jwks.keys),
+						Err(_) => {
+							keys.push(serde_json::from_str(&content).map_err(Error::DeserError)?)
+						}
+					}
+				}
+				Some("pem") => {
+					let content = fs::read_to_string(&path).map_err(error)?;
+					let kid = path.file_stem().and_then(|stem| stem.to_str());
+					match pem_key(&content, kid) {
+						Some(key) => keys.push(key),
+						None => tracing::warn!("Skipping {} which is not a public key", name),
+					}
+				}
+				_ => (),
+			}
+		}
+		Ok(keys)
+	}
+
+	/// Version of the directory content: target of the `..data` symlink swapped atomically by
+	/// the kubelet, or the last modification time of the files out of Kubernetes
+	fn version(&self) -> Option<String> {
+		if let Ok(target) = fs::read_link(self.dir.join("..data")) {
+			return Some(target.display().to_string());
+		}
+		fs::read_dir(&self.dir)
+			.ok()?
+			.filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+			.max()
+			.map(|modified| format!("{:?}", modified))
+	}
+
+	/// Reload the keys of the Jwt when the directory content changes, checking at the given
+	/// interval. The Jwt must use this store. Must be called from the actix runtime
+	pub fn watch(&self, jwt: Jwt, interval: Duration) {
+		let keys = self.clone();
+		rt::spawn(async move {
+			let mut version = keys.version();
+			loop {
+				rt::time::sleep(interval).await;
+				let current = keys.version();
+				if current != version {
+					tracing::info!("Reloading the keys of {}", keys.dir.display());
+					match jwt.set_keys().await {
+						Ok(()) => version = current,
+						Err(e) => tracing::warn!("Failed to reload the keys: {}", e),
+					}
+				}
+			}
+		});
+	}
+}
+
+impl KeyStore for DirectoryKeys {
+	fn keys(&self) -> LocalBoxFuture<'_, Result<Vec<jwk::JsonWebKey>>> {
+		Box::pin(async move { self.read() })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::keys::thumbprint;
+
+	#[test]
+	fn read_directory() {
+		let keys = DirectoryKeys::new("tests/fixtures").read().unwrap();
+		let kid = |kid: &str| {
+			keys.iter()
+				.find(|key| key.key_id.as_deref() == Some(kid))
+				.unwrap()
+		};
+		// same key as a JWK and as a PEM
+		assert_eq!(thumbprint(kid("public")), thumbprint(kid("test-key")));
+	}
+}
@@ -0,0 +1,448 @@
+use crate::{
+	data::Jwks,
+	result::{Error, Result},
+};
+
+use futures_util::future::LocalBoxFuture;
+use jsonwebkey as jwk;
+use jsonwebtoken as jwt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+	collections::HashMap,
+	future::Future,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, RwLock, RwLockReadGuard,
+	},
+	time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::Mutex;
+
+/// Source of the keys checking the signature of the tokens, used instead of the jwks endpoint
+/// when set on a Jwt
+pub trait KeyStore {
+	/// Fetch the current keys
+	fn keys(&self) -> LocalBoxFuture<'_, Result<Vec<jwk::JsonWebKey>>>;
+}
+
+/// A key with its decoding key, parsed once when the keys are loaded instead of on each request
+pub(crate) struct LoadedKey {
+	pub(crate) jwk: jwk::JsonWebKey,
+	pub(crate) decoding: jwt::DecodingKey,
+	pub(crate) thumbprint: Option<String>,
+	// curve of EC keys, key type of the others, to infer the algorithm of keys without alg
+	pub(crate) kind: Option<String>,
+	// validation of the tokens signed with keys having an alg. Only the algorithm is fixed at
+	// load time: aud, iss and the leeway belong to the Jwt (and to the claim overrides of the
+	// requests), so they are checked by check_claims and check_time
+	pub(crate) validation: Option<jwt::Validation>,
+}
+
+impl LoadedKey {
+	/// Parse the keys of a set and index them by kid, or by thumbprint for the keys without kid
+	pub(crate) fn load(keys: Vec<jwk::JsonWebKey>) -> HashMap<String, Arc<Self>> {
+		keys.into_iter()
+			.filter_map(|jwk| {
+				let thumbprint = thumbprint(&jwk);
+				let Some(kid) = jwk.key_id.clone().or_else(|| thumbprint.clone()) else {
+					tracing::warn!("Skipping a key without kid");
+					return None;
+				};
+				let key = Self {
+					decoding: jwk.key.to_decoding_key(),
+					thumbprint,
+					kind: kind(&jwk),
+					validation: jwk.algorithm.map(|alg| validation(alg.into())),
+					jwk,
+				};
+				Some((kid, Arc::new(key)))
+			})
+			.collect()
+	}
+}
+
+/// Validation of the signature of the tokens signed with the given algorithm, without any claim
+pub(crate) fn validation(alg: jwt::Algorithm) -> jwt::Validation {
+	let mut validation = jwt::Validation::new(alg);
+	// the time claims are checked against the clock of the Jwt and the other claims by
+	// check_claims
+	validation.validate_exp = false;
+	validation.validate_nbf = false;
+	validation
+}
+
+/// Curve of an EC key or type of another key
+fn kind(key: &jwk::JsonWebKey) -> Option<String> {
+	let value = serde_json::to_value(&*key.key).ok()?;
+	value
+		.get("crv")
+		.or_else(|| value.get("kty"))?
+		.as_str()
+		.map(|kind| kind.to_owned())
+}
+
+/// RFC 7638 thumbprint of a key (base64url encoded SHA-256 digest of its required members)
+pub fn thumbprint(key: &jwk::JsonWebKey) -> Option<String> {
+	let value = serde_json::to_value(&*key.key).ok()?;
+	let members: &[&str] = match value.get("kty")?.as_str()? {
+		"EC" => &["crv", "kty", "x", "y"],
+		"RSA" => &["e", "kty", "n"],
+		"oct" => &["k", "kty"],
+		_ => return None,
+	};
+	// members in lexicographic order without whitespace
+	let canonical = members
+		.iter()
+		.map(|member| {
+			let value = value.get(*member)?.as_str()?;
+			Some(format!(
+				"\"{}\":{}",
+				member,
+				serde_json::to_string(value).ok()?
+			))
+		})
+		.collect::<Option<Vec<_>>>()?
+		.join(",");
+	let digest = Sha256::digest(format!("{{{}}}", canonical).as_bytes());
+	Some(base64::encode_config(digest, base64::URL_SAFE_NO_PAD))
+}
+
+/// Minimum strength of the keys accepted from a key set. Weaker keys are skipped at load time
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct KeyPolicy {
+	/// minimum size in bits of the RSA moduli
+	pub min_rsa_bits: usize,
+	/// accepted elliptic curves
+	pub curves: Vec<String>,
+}
+
+impl Default for KeyPolicy {
+	fn default() -> Self {
+		Self {
+			min_rsa_bits: 2048,
+			curves: vec!["P-256".to_owned(), "P-384".to_owned(), "P-521".to_owned()],
+		}
+	}
+}
+
+impl KeyPolicy {
+	/// Accept any key (tests only)
+	pub fn permissive() -> Self {
+		Self {
+			min_rsa_bits: 0,
+			curves: Vec::new(),
+		}
+	}
+
+	/// Return the reason why the key is too weak if any
+	pub fn check(&self, key: &jwk::JsonWebKey) -> Option<String> {
+		let value = serde_json::to_value(&*key.key).ok()?;
+		match value.get("kty")?.as_str()? {
+			"RSA" => {
+				let n = base64::decode_config(value.get("n")?.as_str()?, base64::URL_SAFE_NO_PAD)
+					.ok()?;
+				let bits = modulus_bits(&n);
+				(bits < self.min_rsa_bits).then(|| format!("RSA modulus of {} bits", bits))
+			}
+			"EC" if !self.curves.is_empty() => {
+				let crv = value.get("crv")?.as_str()?;
+				(!self.curves.iter().any(|curve| curve == crv))
+					.then(|| format!("curve {} not approved", crv))
+			}
+			_ => None,
+		}
+	}
+
+	/// Remove the weak keys, warning about each of them
+	pub fn filter(&self, keys: Vec<jwk::JsonWebKey>) -> Vec<jwk::JsonWebKey> {
+		let mut skipped = Vec::new();
+		let keys = keys
+			.into_iter()
+			.filter(|key| match self.check(key) {
+				Some(reason) => {
+					let kid = key.key_id.as_deref().unwrap_or("<no kid>");
+					skipped.push(format!("{} ({})", kid, reason));
+					false
+				}
+				None => true,
+			})
+			.collect();
+		if !skipped.is_empty() {
+			tracing::warn!("Skipping weak keys: {}", skipped.join(", "));
+		}
+		keys
+	}
+}
+
+/// Size in bits of a big endian modulus
+fn modulus_bits(n: &[u8]) -> usize {
+	match n.iter().position(|byte| *byte != 0) {
+		Some(first) => (n.len() - first) * 8 - n[first].leading_zeros() as usize,
+		None => 0,
+	}
+}
+
+/// Kids added to and removed from a key set (eg. on a rotation by the identity provider)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyChange {
+	/// Kids of the new keys
+	pub added: Vec<String>,
+	/// Kids of the keys no longer published
+	pub removed: Vec<String>,
+}
+
+impl KeyChange {
+	/// Return true if the kids didn't change
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
+}
+
+/// Callback notified of the changes of a key set
+pub type KeyListener = Arc<dyn Fn(&KeyChange) + Send + Sync>;
+
+/// Keys indexed by kid, shared by all the clones of a Jwt (and its middlewares) so that keys
+/// updated at runtime by a refresher or an admin handler are seen by all of them
+#[derive(Clone, Default)]
+pub struct KeySet {
+	keys: Arc<RwLock<HashMap<String, Arc<LoadedKey>>>>,
+	listeners: Arc<RwLock<Vec<KeyListener>>>,
+}
+
+impl KeySet {
+	/// Construct a set from the given keys
+	pub fn new(keys: Vec<jwk::JsonWebKey>) -> Self {
+		Self {
+			keys: Arc::new(RwLock::new(LoadedKey::load(keys))),
+			..Self::default()
+		}
+	}
+
+	/// Call the given function each time kids are added or removed (called after the update,
+	/// from the task performing it)
+	pub fn on_change<F>(&self, listener: F)
+	where
+		F: Fn(&KeyChange) + Send + Sync + 'static,
+	{
+		self.listeners.write().unwrap().push(Arc::new(listener));
+	}
+
+	/// Replace all the keys
+	pub fn replace(&self, keys: Vec<jwk::JsonWebKey>) {
+		let keys = LoadedKey::load(keys);
+		let mut current = self.keys.write().unwrap();
+		let mut change = KeyChange {
+			added: keys
+				.keys()
+				.filter(|kid| !current.contains_key(*kid))
+				.cloned()
+				.collect(),
+			removed: current
+				.keys()
+				.filter(|kid| !keys.contains_key(*kid))
+				.cloned()
+				.collect(),
+		};
+		*current = keys;
+		drop(current);
+		change.added.sort();
+		change.removed.sort();
+		self.notify(change);
+	}
+
+	/// Add or replace a key and return false if it can't be indexed (no kid nor thumbprint)
+	pub fn insert(&self, key: jwk::JsonWebKey) -> bool {
+		let loaded = LoadedKey::load(vec![key]);
+		let Some((kid, key)) = loaded.into_iter().next() else {
+			return false;
+		};
+		let added = self
+			.keys
+			.write()
+			.unwrap()
+			.insert(kid.clone(), key)
+			.is_none();
+		if added {
+			self.notify(KeyChange {
+				added: vec![kid],
+				removed: Vec::new(),
+			});
+		}
+		true
+	}
+
+	/// Remove a key and return true if it was present
+	pub fn remove(&self, kid: &str) -> bool {
+		let removed = self.keys.write().unwrap().remove(kid).is_some();
+		if removed {
+			self.notify(KeyChange {
+				added: Vec::new(),
+				removed: vec![kid.to_owned()],
+			});
+		}
+		removed
+	}
+
+	/// Return the key with the given kid
+	pub fn jwk(&self, kid: &str) -> Option<jwk::JsonWebKey> {
+		self.get(kid).map(|key| key.jwk.clone())
+	}
+
+	/// Return the kids of the keys
+	pub fn kids(&self) -> Vec<String> {
+		self.keys.read().unwrap().keys().cloned().collect()
+	}
+
+	/// Number of keys
+	pub fn len(&self) -> usize {
+		self.keys.read().unwrap().len()
+	}
+
+	/// Return true if there is no key
+	pub fn is_empty(&self) -> bool {
+		self.keys.read().unwrap().is_empty()
+	}
+
+	/// Return the loaded key with the given kid
+	pub(crate) fn get(&self, kid: &str) -> Option<Arc<LoadedKey>> {
+		self.keys.read().unwrap().get(kid).cloned()
+	}
+
+	/// Lock the keys for reading
+	pub(crate) fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<LoadedKey>>> {
+		self.keys.read().unwrap()
+	}
+
+	/// Notify the listeners of a change
+	fn notify(&self, change: KeyChange) {
+		if change.is_empty() {
+			return;
+		}
+		tracing::info!(added = ?change.added, removed = ?change.removed, "key set changed");
+		for listener in self.listeners.read().unwrap().iter() {
+			listener(&change);
+		}
+	}
+}
+
+// lifetime of the keys fetched from a jku url
+const JKU_TTL: Duration = Duration::from_secs(3600);
+// maximum number of jku urls cached
+const JKU_CAPACITY: usize = 100;
+
+/// Keys fetched on demand from the jku urls of the tokens
+#[derive(Default)]
+pub(crate) struct JkuCache {
+	sets: RwLock<HashMap<String, (HashMap<String, Arc<LoadedKey>>, Instant)>>,
+}
+
+impl JkuCache {
+	/// Return the key of the given url and kid if it was fetched less than an hour ago
+	pub(crate) fn get(&self, url: &str, kid: &str) -> Option<Arc<LoadedKey>> {
+		let sets = self.sets.read().unwrap();
+		let (keys, fetched) = sets.get(url)?;
+		if fetched.elapsed() > JKU_TTL {
+			return None;
+		}
+		keys.get(kid).cloned()
+	}
+
+	/// Store the keys fetched from the given url, evicting the oldest url when full
+	pub(crate) fn insert(&self, url: &str, keys: Vec<jwk::JsonWebKey>) {
+		let mut sets = self.sets.write().unwrap();
+		if sets.len() >= JKU_CAPACITY && !sets.contains_key(url) {
+			let oldest = sets
+				.iter()
+				.min_by_key(|(_, (_, fetched))| *fetched)
+				.map(|(url, _)| url.clone());
+			if let Some(oldest) = oldest {
+				sets.remove(&oldest);
+			}
+		}
+		sets.insert(url.to_owned(), (LoadedKey::load(keys), Instant::now()));
+	}
+}
+
+/// Rate limit of the on-demand fetches of keys (jku, x5u) by host, so that tokens designating
+/// many urls on a trusted host can't trigger a fetch each
+#[derive(Default)]
+pub(crate) struct FetchThrottle {
+	fetches: std::sync::Mutex<HashMap<String, Instant>>,
+}
+
+impl FetchThrottle {
+	/// Return true and record the fetch if the host was not fetched from during the interval
+	pub(crate) fn allow(&self, host: &str, interval: Duration) -> bool {
+		let host = host.to_ascii_lowercase();
+		let mut fetches = self.fetches.lock().unwrap();
+		if fetches.get(&host).is_some_and(|at| at.elapsed() < interval) {
+			return false;
+		}
+		// the hosts are trusted ones, this only drops the stale entries
+		fetches.retain(|_, at| at.elapsed() < interval);
+		fetches.insert(host, Instant::now());
+		true
+	}
+}
+
+/// Coalescing of concurrent reloads of the keys: callers arriving while a reload is running wait
+/// for it and share its outcome instead of fetching the keys again
+#[derive(Default)]
+pub(crate) struct SingleFlight {
+	// number of completed reloads
+	generation: AtomicU64,
+	// completion time and error message of the last reload
+	last: Mutex<Option<(Instant, Option<String>)>>,
+	// same as last, readable while a reload is running
+	status: std::sync::Mutex<Option<(SystemTime, Option<String>)>>,
+}
+
+impl SingleFlight {
+	/// Run the reload unless another one completed while waiting, or less than `min` ago if
+	/// given, in which case its outcome is returned
+	pub(crate) async fn run<F>(&self, min: Option<Duration>, reload: F) -> Result<()>
+	where
+		F: Future<Output = Result<()>>,
+	{
+		let seen = self.generation.load(Ordering::Acquire);
+		let mut last = self.last.lock().await;
+		let recent = matches!((&*last, min), (Some((at, _)), Some(min)) if at.elapsed() < min);
+		if self.generation.load(Ordering::Acquire) != seen || recent {
+			return match &*last {
+				Some((_, Some(e))) => Err(Error::Refresh(e.to_owned())),
+				_ => Ok(()),
+			};
+		}
+		let res = reload.await;
+		let error = res.as_ref().err().map(ToString::to_string);
+		*self.status.lock().unwrap() = Some((SystemTime::now(), error.clone()));
+		*last = Some((Instant::now(), error));
+		self.generation.fetch_add(1, Ordering::Release);
+		res
+	}
+
+	/// Completion time and error message of the last reload
+	pub(crate) fn status(&self) -> Option<(SystemTime, Option<String>)> {
+		self.status.lock().unwrap().clone()
+	}
+}
+
+/// Fixed set of keys (tests, keys distributed out of band)
+#[derive(Clone, Debug, Default)]
+pub struct StaticKeys(pub Vec<jwk::JsonWebKey>);
+
+impl StaticKeys {
+	/// Parse the keys of a JWKS document
+	pub fn from_json(jwks: &str) -> Result<Self> {
+		Ok(Self(Jwks::parse(jwks)?.keys))
+	}
+}
+
+impl KeyStore for StaticKeys {
+	fn keys(&self) -> LocalBoxFuture<'_, Result<Vec<jwk::JsonWebKey>>> {
+		let keys = self.0.clone();
+		Box::pin(async move { Ok(keys) })
+	}
+}
@@ -1,3 +1,46 @@
+pub mod cache;
+#[cfg(feature = "casbin")]
+pub mod casbin;
+#[cfg(feature = "cedar")]
+pub mod cedar;
+pub mod clock;
 pub mod middleware;
 pub mod data;
+pub mod config;
+pub mod exchange;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handler;
+pub mod keys;
+pub mod outbound;
+pub mod presets;
 pub mod result;
+pub mod roles;
+pub mod signer;
+pub mod tokens;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod userinfo;
+#[cfg(feature = "k8s")]
+pub mod k8s;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod pem;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "vault")]
+pub mod vault;
+mod x5u;
+
+// canonical api (the middlewares target actix-web 4 only)
+pub use config::AuthConfig;
+pub use data::{ClaimOverride, Claims, Jwt};
+pub use middleware::{
+	anyauth::AnyAuth,
+	basicauth::BasicAuth,
+	chain::{AuthChain, AuthContext, Authenticator, Policy},
+	jwtauth::JwtAuth,
+	tokenauth::TokenAuth,
+};
+pub use result::{Error, Result};
+pub use tokens::{Secret, TokenIdentity, TokenSet};
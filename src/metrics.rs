@@ -0,0 +1,62 @@
+use prometheus::{Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry};
+use std::time::Duration;
+
+/// Prometheus metrics of the authentication middlewares
+#[derive(Clone)]
+pub struct Metrics {
+	success: IntCounter,
+	failure: IntCounterVec,
+	latency: Histogram,
+	refresh: IntCounterVec,
+}
+
+impl Metrics {
+	/// Create the metrics and register them on the given registry
+	pub fn new(registry: &Registry) -> prometheus::Result<Self> {
+		let success = IntCounter::new("auth_success_total", "Number of accepted requests")?;
+		let failure = IntCounterVec::new(
+			Opts::new("auth_failure_total", "Number of rejected requests"),
+			&["reason"],
+		)?;
+		let latency = Histogram::with_opts(HistogramOpts::new(
+			"auth_validation_duration_seconds",
+			"Time spent validating tokens",
+		))?;
+		let refresh = IntCounterVec::new(
+			Opts::new("jwks_refresh_total", "Number of jwks reloads"),
+			&["outcome"],
+		)?;
+		registry.register(Box::new(success.clone()))?;
+		registry.register(Box::new(failure.clone()))?;
+		registry.register(Box::new(latency.clone()))?;
+		registry.register(Box::new(refresh.clone()))?;
+		Ok(Self {
+			success,
+			failure,
+			latency,
+			refresh,
+		})
+	}
+
+	/// Record an accepted request
+	pub fn success(&self) {
+		self.success.inc();
+	}
+
+	/// Record a rejected request
+	pub fn failure(&self, reason: &str) {
+		self.failure.with_label_values(&[reason]).inc();
+	}
+
+	/// Record the time spent validating a token
+	pub fn latency(&self, elapsed: Duration) {
+		self.latency.observe(elapsed.as_secs_f64());
+	}
+
+	/// Record a jwks reload
+	pub fn refresh(&self, ok: bool) {
+		self.refresh
+			.with_label_values(&[if ok { "success" } else { "failure" }])
+			.inc();
+	}
+}
@@ -0,0 +1,69 @@
+use super::{
+	chain::{AuthChain, AuthChainMiddleware, Policy},
+	jwtauth::JwtAuth,
+	tokenauth::TokenAuth,
+};
+use crate::data::Jwt;
+
+use actix_web::{
+	dev::{Service, ServiceRequest, ServiceResponse, Transform},
+	Error,
+};
+
+/// Middleware factory accepting a request as soon as one of the configured mechanisms does
+/// (a JWT in `Authorization: Bearer` or a static token in the `token` header)
+#[derive(Clone)]
+pub struct AnyAuth(AuthChain);
+
+impl Default for AnyAuth {
+	fn default() -> Self {
+		Self(AuthChain::new(Policy::FirstMatch))
+	}
+}
+
+impl AnyAuth {
+	/// Construct an AnyAuth rejecting every request until a mechanism is added
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Accept requests with a valid JWT
+	pub fn jwt(self, jwt: Jwt) -> Self {
+		self.jwt_auth(JwtAuth::new(jwt))
+	}
+
+	/// Accept requests validated by the given JwtAuth (rejections are rendered by it)
+	pub fn jwt_auth(self, auth: JwtAuth) -> Self {
+		Self(self.0.with(auth))
+	}
+
+	/// Accept requests carrying the given static token
+	pub fn token(self, token: &str) -> Self {
+		self.token_auth(TokenAuth::new(token))
+	}
+
+	/// Accept requests validated by the given TokenAuth
+	pub fn token_auth(self, auth: TokenAuth) -> Self {
+		Self(self.0.with(auth))
+	}
+}
+
+// Middleware factory is `Transform` trait from actix-service crate
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for AnyAuth
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+	S::Future: 'static,
+	B: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Transform = AuthChainMiddleware<S>;
+	type InitError = ();
+	type Future = <AuthChain as Transform<S, ServiceRequest>>::Future;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		self.0.new_transform(service)
+	}
+}
@@ -0,0 +1,142 @@
+use crate::result::Error as AuthError;
+
+use actix_web::dev::ServiceRequest;
+use hmac::{Hmac, Mac};
+use jsonwebtoken as jwt;
+use serde_json::Value;
+use sha2::Sha256;
+use std::borrow::Cow;
+
+// target of the audit events so that they can be filtered and routed separately
+const TARGET: &str = "actix_token_middleware::audit";
+
+/// How the token subject appears in audit events (hidden by default as it is personal data)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Redaction {
+	/// Log the subject as is
+	Clear,
+	/// Only log the first characters of the subject
+	Partial,
+	/// Never log the subject
+	#[default]
+	Hidden,
+}
+
+impl Redaction {
+	/// Apply the redaction policy to a subject
+	pub(crate) fn apply<'a>(&self, sub: &'a str) -> Option<Cow<'a, str>> {
+		match self {
+			Self::Clear => Some(Cow::Borrowed(sub)),
+			Self::Partial => Some(Cow::Owned(format!(
+				"{}***",
+				sub.chars().take(3).collect::<String>()
+			))),
+			Self::Hidden => None,
+		}
+	}
+}
+
+/// Salt of the token fingerprints logged with the rejections, so that the repeated use of a
+/// token can be correlated without logging it. Random by default (fingerprints then differ
+/// between processes), set the same salt on all the replicas to correlate them across replicas
+#[derive(Clone)]
+pub struct FingerprintSalt(Vec<u8>);
+
+impl Default for FingerprintSalt {
+	fn default() -> Self {
+		let mut salt = vec![0; 32];
+		getrandom::getrandom(&mut salt).expect("the os provides random bytes");
+		Self(salt)
+	}
+}
+
+impl FingerprintSalt {
+	/// Use the given salt
+	pub fn new(salt: &str) -> Self {
+		Self(salt.as_bytes().to_vec())
+	}
+
+	/// Short salted hash of a token (first 8 bytes of an HMAC-SHA256 in hex)
+	pub fn fingerprint(&self, token: &str) -> String {
+		let signature = Hmac::<Sha256>::new_from_slice(&self.0)
+			.expect("hmac accepts keys of any size")
+			.chain_update(token.as_bytes())
+			.finalize()
+			.into_bytes();
+		signature[..8]
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect()
+	}
+}
+
+/// Record the outcome and the token fields on the current span. The header fields are recorded
+/// for any decodable token, the claims only once the token is validated
+pub fn record(
+	outcome: &str,
+	header: Option<&jwt::Header>,
+	claims: Option<&Value>,
+	redaction: Redaction,
+) {
+	let span = tracing::Span::current();
+	span.record("outcome", outcome);
+	if let Some(header) = header {
+		span.record("kid", header.kid.as_deref());
+		span.record("alg", tracing::field::debug(header.alg));
+	}
+	if let Some(claims) = claims {
+		span.record("iss", claims.get("iss").and_then(Value::as_str));
+		let sub = claims
+			.get("sub")
+			.and_then(Value::as_str)
+			.and_then(|sub| redaction.apply(sub));
+		span.record("sub", sub.as_deref());
+	}
+}
+
+/// Emit an event for an accepted request
+pub fn accepted(req: &ServiceRequest, tokendata: &jwt::TokenData<Value>, redaction: Redaction) {
+	let sub = tokendata
+		.claims
+		.get("sub")
+		.and_then(Value::as_str)
+		.and_then(|sub| redaction.apply(sub));
+	tracing::info!(
+		target: TARGET,
+		outcome = "accepted",
+		kid = tokendata.header.kid.as_deref(),
+		sub = sub.as_deref(),
+		method = %req.method(),
+		path = req.path(),
+		peer = req.peer_addr().map(|addr| addr.ip().to_string()).as_deref(),
+		"authentication accepted"
+	);
+}
+
+/// Emit an event for a rejected request. The error message, which can hold the values of the
+/// claims, is only logged when the subjects are logged in clear
+pub fn rejected(
+	req: &ServiceRequest,
+	token: Option<&str>,
+	e: &AuthError,
+	salt: &FingerprintSalt,
+	redaction: Redaction,
+) {
+	let kid = token
+		.and_then(|token| jwt::decode_header(token).ok())
+		.and_then(|header| header.kid);
+	let fingerprint = token.map(|token| salt.fingerprint(token));
+	let message = (redaction == Redaction::Clear).then(|| e.to_string());
+	tracing::warn!(
+		target: TARGET,
+		outcome = "rejected",
+		reason = e.reason(),
+		message = message.as_deref(),
+		kid = kid.as_deref(),
+		fingerprint = fingerprint.as_deref(),
+		method = %req.method(),
+		path = req.path(),
+		peer = req.peer_addr().map(|addr| addr.ip().to_string()).as_deref(),
+		"authentication rejected"
+	);
+}
@@ -0,0 +1,223 @@
+use super::{
+	chain::{self, AuthContext, Authenticator},
+	reject::quoted,
+};
+use crate::{
+	result::{Error as AuthError, Result as AuthResult},
+	tokens::{Secret, TokenIdentity},
+};
+
+use actix_utils::future::{ready, Ready};
+use actix_web::{
+	dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+	error::InternalError,
+	http::header::{AUTHORIZATION, WWW_AUTHENTICATE},
+	web, Error, HttpMessage, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use std::{fs, path::Path, rc::Rc, str::FromStr, sync::Arc};
+use subtle::ConstantTimeEq;
+
+/// Middleware factory checking HTTP Basic credentials, for legacy clients that can't send a
+/// token. The identity of the user is inserted in the request extensions
+#[derive(Clone, Debug)]
+pub struct BasicAuth {
+	users: Arc<Vec<(String, Secret)>>,
+	realm: String,
+}
+
+impl BasicAuth {
+	/// Construct a `BasicAuth` middleware without users for the given realm
+	pub fn new(realm: &str) -> Self {
+		Self {
+			users: Arc::default(),
+			realm: realm.to_owned(),
+		}
+	}
+
+	/// Accept the given user and secret (plain text or hash)
+	pub fn user(mut self, name: &str, secret: Secret) -> Self {
+		Arc::make_mut(&mut self.users).push((name.to_owned(), secret));
+		self
+	}
+
+	/// Secret checked when the user is unknown, so that the response time doesn't tell which
+	/// users exist: the first slow hash if any (with the cost of the real ones), or the first
+	/// secret
+	fn dummy(&self) -> Option<&Secret> {
+		self.users
+			.iter()
+			.find(|(_, secret)| secret.is_slow())
+			.or_else(|| self.users.first())
+			.map(|(_, secret)| secret)
+	}
+
+	/// Load the users of an htpasswd-style file (`user:secret` per line). Only bcrypt, argon2
+	/// and `sha256:` hashes are supported: the other formats (crypt, `{SHA}`, `{SSHA}`, `$apr1$`,
+	/// `$5$`, `$6$`...) and plain text are rejected
+	pub fn htpasswd<P: AsRef<Path>>(mut self, path: P) -> AuthResult<Self> {
+		let content = fs::read_to_string(path).map_err(AuthError::TokenFile)?;
+		let users = Arc::make_mut(&mut self.users);
+		for line in content
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		{
+			let (name, secret) = line.split_once(':').ok_or(AuthError::InvalidSecret(
+				"htpasswd entries must be user:secret",
+			))?;
+			// unknown hashes would otherwise be taken for plain text secrets
+			match Secret::from_str(secret)? {
+				Secret::Plain(_) => {
+					return Err(AuthError::InvalidSecret("unsupported htpasswd hash"))
+				}
+				secret => users.push((name.to_owned(), secret)),
+			}
+		}
+		Ok(self)
+	}
+
+	/// Decode the `Authorization: Basic` credentials of the request
+	fn credentials(req: &ServiceRequest) -> Option<(String, String)> {
+		let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+		let encoded = chain::credentials(value, "Basic")?;
+		let decoded = String::from_utf8(base64::decode(encoded).ok()?).ok()?;
+		decoded
+			.split_once(':')
+			.map(|(user, password)| (user.to_owned(), password.to_owned()))
+	}
+
+	/// Check the credentials of the request and return the user name. Hashes are verified on
+	/// the blocking thread pool
+	pub(crate) async fn check(&self, req: &ServiceRequest) -> Result<String, AuthError> {
+		let (user, password) = Self::credentials(req).ok_or(AuthError::NoCredentials)?;
+		// compare every user name so that the time doesn't depend on the matching position
+		let secret = self.users.iter().fold(None, |found, (name, secret)| {
+			if bool::from(name.as_bytes().ct_eq(user.as_bytes())) {
+				Some(secret)
+			} else {
+				found
+			}
+		});
+		let (known, secret) = match secret.or_else(|| self.dummy()) {
+			Some(found) => (secret.is_some(), found.clone()),
+			None => return Err(AuthError::InvalidCredentials),
+		};
+		let valid = web::block(move || secret.verify(&password))
+			.await
+			.unwrap_or(false);
+		if known && valid {
+			Ok(user)
+		} else {
+			Err(AuthError::InvalidCredentials)
+		}
+	}
+
+	/// Build the 401 response asking for credentials
+	fn response(&self, e: &AuthError) -> HttpResponse {
+		HttpResponse::Unauthorized()
+			.insert_header((
+				WWW_AUTHENTICATE,
+				format!("Basic realm=\"{}\", charset=\"UTF-8\"", quoted(&self.realm)),
+			))
+			.content_type("text/plain; charset=utf-8")
+			.body(format!("Not authorized - {}", e))
+	}
+}
+
+impl Authenticator for BasicAuth {
+	fn authenticate<'a>(
+		&'a self,
+		req: &'a mut ServiceRequest,
+	) -> LocalBoxFuture<'a, Result<AuthContext, AuthError>> {
+		Box::pin(async move { self.check(req).await.map(AuthContext::Basic) })
+	}
+
+	fn reject(&self, e: AuthError, _req: &ServiceRequest) -> Error {
+		let res = self.response(&e);
+		InternalError::from_response(e, res).into()
+	}
+}
+
+// Middleware factory is `Transform` trait from actix-service crate
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for BasicAuth
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+	S::Future: 'static,
+	B: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Transform = BasicAuthMiddleware<S>;
+	type InitError = ();
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ready(Ok(BasicAuthMiddleware {
+			service: Rc::new(service),
+			auth: self.clone(),
+		}))
+	}
+}
+
+pub struct BasicAuthMiddleware<S> {
+	service: Rc<S>,
+	auth: BasicAuth,
+}
+
+impl<S, B> Service<ServiceRequest> for BasicAuthMiddleware<S>
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+	S::Future: 'static,
+	B: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	forward_ready!(service);
+
+	fn call(&self, req: ServiceRequest) -> Self::Future {
+		let service = self.service.clone();
+		let auth = self.auth.clone();
+		Box::pin(async move {
+			match auth.check(&req).await {
+				Ok(user) => {
+					req.extensions_mut().insert(TokenIdentity::new(&user));
+					service.call(req).await
+				}
+				Err(e) => Err(auth.reject(e, &req)),
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+
+	#[actix_rt::test]
+	async fn check_credentials() {
+		let auth = BasicAuth::new("test").user("alice", Secret::sha256("secret"));
+		let req = TestRequest::default()
+			.insert_header((
+				AUTHORIZATION,
+				format!("Basic {}", base64::encode("alice:secret")),
+			))
+			.to_srv_request();
+		assert_eq!(auth.check(&req).await.unwrap(), "alice");
+		let req = TestRequest::default()
+			.insert_header((
+				AUTHORIZATION,
+				format!("Basic {}", base64::encode("bob:secret")),
+			))
+			.to_srv_request();
+		assert!(matches!(
+			auth.check(&req).await,
+			Err(AuthError::InvalidCredentials)
+		));
+	}
+}
@@ -0,0 +1,187 @@
+use crate::{result::Error as AuthError, tokens::TokenIdentity};
+
+use actix_utils::future::{ok, Ready};
+use actix_web::{
+	dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+	error::ErrorUnauthorized,
+	Error, HttpMessage,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::Value;
+use std::{rc::Rc, sync::Arc};
+
+/// Return the credentials following the given authentication scheme (case insensitive) in an
+/// `Authorization`-like header value
+pub(crate) fn credentials<'a>(value: &'a str, scheme: &str) -> Option<&'a str> {
+	value
+		.split_once(' ')
+		.filter(|(s, _)| s.eq_ignore_ascii_case(scheme))
+		.map(|(_, credentials)| credentials.trim())
+}
+
+/// Identity established by an authenticator
+#[derive(Clone, Debug)]
+pub enum AuthContext {
+	/// Claims of a validated JWT
+	Jwt(Value),
+	/// Request carrying a static token, with the identity of its client
+	Token(TokenIdentity),
+	/// Request carrying valid HTTP Basic credentials, with the user name
+	Basic(String),
+	/// Request let through without credentials
+	Anonymous,
+}
+
+/// An authentication mechanism that can be combined with others in an AuthChain
+pub trait Authenticator {
+	/// Authenticate the request
+	fn authenticate<'a>(
+		&'a self,
+		req: &'a mut ServiceRequest,
+	) -> LocalBoxFuture<'a, Result<AuthContext, AuthError>>;
+
+	/// Convert an authentication error to an actix error
+	fn reject(&self, _e: AuthError, _req: &ServiceRequest) -> Error {
+		ErrorUnauthorized("not authorized")
+	}
+}
+
+/// How the results of the authenticators of a chain are combined
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Policy {
+	/// Accept the request as soon as one authenticator does
+	#[default]
+	FirstMatch,
+	/// Accept the request only if all the authenticators do
+	AllMustPass,
+}
+
+/// Middleware factory combining several authenticators. The context of the first successful
+/// authenticator is inserted in the request extensions
+#[derive(Clone, Default)]
+pub struct AuthChain {
+	authenticators: Vec<Arc<dyn Authenticator + Send + Sync>>,
+	policy: Policy,
+}
+
+impl AuthChain {
+	/// Construct an empty chain (rejecting every request) with the given policy
+	pub fn new(policy: Policy) -> Self {
+		Self {
+			authenticators: Vec::new(),
+			policy,
+		}
+	}
+
+	/// Append an authenticator to the chain
+	pub fn with<A>(mut self, authenticator: A) -> Self
+	where
+		A: Authenticator + Send + Sync + 'static,
+	{
+		self.authenticators.push(Arc::new(authenticator));
+		self
+	}
+
+	/// Run the authenticators according to the policy and return the context or the error
+	/// with the index of the authenticator that produced it
+	async fn authenticate(
+		&self,
+		req: &mut ServiceRequest,
+	) -> Result<AuthContext, (usize, AuthError)> {
+		let mut context = None;
+		let mut error = None;
+		for (i, authenticator) in self.authenticators.iter().enumerate() {
+			match authenticator.authenticate(req).await {
+				Ok(ctx) => {
+					if self.policy == Policy::FirstMatch {
+						return Ok(ctx);
+					}
+					context.get_or_insert(ctx);
+				}
+				Err(e) => {
+					if self.policy == Policy::AllMustPass {
+						return Err((i, e));
+					}
+					// missing credentials are less relevant than invalid ones
+					match error {
+						Some((
+							_,
+							AuthError::NoBearer | AuthError::NoToken | AuthError::NoCredentials,
+						))
+						| None => error = Some((i, e)),
+						_ => (),
+					}
+				}
+			}
+		}
+		match (context, error) {
+			(Some(ctx), _) => Ok(ctx),
+			(None, Some(e)) => Err(e),
+			// empty chain
+			(None, None) => Err((0, AuthError::NoToken)),
+		}
+	}
+
+	/// Convert an authentication error to an actix error with the authenticator that produced it
+	fn reject(&self, i: usize, e: AuthError, req: &ServiceRequest) -> Error {
+		match self.authenticators.get(i) {
+			Some(authenticator) => authenticator.reject(e, req),
+			None => ErrorUnauthorized("not authorized"),
+		}
+	}
+}
+
+// Middleware factory is `Transform` trait from actix-service crate
+// `S` - type of the next service
+// `B` - type of response's body
+impl<S, B> Transform<S, ServiceRequest> for AuthChain
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+	S::Future: 'static,
+	B: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Transform = AuthChainMiddleware<S>;
+	type InitError = ();
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ok(AuthChainMiddleware {
+			service: Rc::new(service),
+			chain: self.clone(),
+		})
+	}
+}
+
+pub struct AuthChainMiddleware<S> {
+	service: Rc<S>,
+	chain: AuthChain,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthChainMiddleware<S>
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+	S::Future: 'static,
+	B: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	forward_ready!(service);
+
+	fn call(&self, mut req: ServiceRequest) -> Self::Future {
+		let service = self.service.clone();
+		let chain = self.chain.clone();
+		Box::pin(async move {
+			match chain.authenticate(&mut req).await {
+				Ok(context) => {
+					req.extensions_mut().insert(context);
+					service.call(req).await
+				}
+				Err((i, e)) => Err(chain.reject(i, e, &req)),
+			}
+		})
+	}
+}
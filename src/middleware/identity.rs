@@ -0,0 +1,64 @@
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+
+/// Mapping of token claims to the request headers seen by upstream handlers
+#[derive(Clone, Debug, Default)]
+pub struct IdentityHeaders(Vec<(HeaderName, String)>);
+
+impl IdentityHeaders {
+	/// Empty mapping
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Set the header `name` to the value of `claim`
+	pub fn header(mut self, name: HeaderName, claim: &str) -> Self {
+		self.0.push((name, claim.to_owned()));
+		self
+	}
+
+	/// Usual headers for gitlab job tokens (`X-Auth-Sub`, `X-Auth-User-Login`, `X-Auth-Project-Path`)
+	pub fn gitlab() -> Self {
+		Self::new()
+			.header(HeaderName::from_static("x-auth-sub"), "sub")
+			.header(HeaderName::from_static("x-auth-user-login"), "user_login")
+			.header(
+				HeaderName::from_static("x-auth-project-path"),
+				"project_path",
+			)
+	}
+
+	/// Return true if no header is configured
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	/// Header values derived from the claims (claims absent or not representable are skipped)
+	pub fn values<'a>(
+		&'a self,
+		claims: &'a Value,
+	) -> impl Iterator<Item = (&'a HeaderName, HeaderValue)> + 'a {
+		self.0.iter().filter_map(move |(name, claim)| {
+			let value = match claims.get(claim)? {
+				Value::String(s) => HeaderValue::from_str(s),
+				value => HeaderValue::from_str(&value.to_string()),
+			};
+			value.ok().map(|value| (name, value))
+		})
+	}
+
+	/// Remove the configured headers so that clients can't forge them
+	pub fn strip(&self, headers: &mut HeaderMap) {
+		for (name, _) in &self.0 {
+			headers.remove(name);
+		}
+	}
+
+	/// Replace the configured headers with the values derived from the claims
+	pub fn inject(&self, headers: &mut HeaderMap, claims: &Value) {
+		self.strip(headers);
+		for (name, value) in self.values(claims) {
+			headers.insert(name.clone(), value);
+		}
+	}
+}
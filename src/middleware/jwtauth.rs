@@ -1,22 +1,1092 @@
-use crate::data::Jwt;
+#[cfg(feature = "otel")]
+use super::otel;
+use super::{
+	audit::{self, FingerprintSalt, Redaction},
+	chain::{self, AuthContext, Authenticator},
+	identity::IdentityHeaders,
+	limiter::FailureLimiter,
+	network::NetworkBypass,
+	reject::{self, ErrorFormat, ErrorTemplate, LoginRedirect},
+};
+#[cfg(feature = "casbin")]
+use crate::casbin::Casbin;
+#[cfg(feature = "cedar")]
+use crate::cedar::Cedar;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::{
+	config::AuthConfig,
+	data::{self, ClaimOverride, Jwt},
+	exchange::SubjectToken,
+	result::{Error as AuthError, Result as AuthResult},
+	roles::Roles,
+	userinfo::Userinfo,
+};
 
-use actix_utils::future::{err, ok, Either, Ready};
+use actix_utils::future::{ok, Ready};
 use actix_web::{
+	cookie::{Cookie, SameSite},
 	dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-	error::ErrorUnauthorized,
-	http::header::AUTHORIZATION,
-	Error,
+	error::InternalError,
+	http::{
+		header::{HeaderName, HeaderValue, AUTHORIZATION, HOST, SEC_WEBSOCKET_PROTOCOL},
+		StatusCode,
+	},
+	rt, web, Error, HttpMessage, HttpRequest, HttpResponse,
 };
-use std::rc::Rc;
+use futures_util::future::LocalBoxFuture;
+use jsonwebtoken as jwt;
+use serde_json::Value;
+use std::{
+	cell::Cell,
+	collections::HashMap,
+	fmt,
+	future::Future,
+	rc::Rc,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
+};
+use tracing::Instrument;
+
+// maximum number of bearer tokens of a request that are validated
+const MAX_CANDIDATES: usize = 3;
+
+/// Callback building the response sent back when a request is rejected
+pub type ErrorHandler = Arc<dyn Fn(&AuthError, &ServiceRequest) -> HttpResponse + Send + Sync>;
+
+/// Where the middleware looks for the token
+#[derive(Clone, Debug)]
+pub enum TokenSource {
+	/// `Bearer` credentials of the given header
+	Header(HeaderName),
+	/// Raw value of the given cookie
+	Cookie(String),
+	/// Subprotocol following the given marker in `Sec-WebSocket-Protocol` (browsers can't set
+	/// headers on websocket upgrades). The marker is echoed back as the accepted subprotocol
+	WebSocket(String),
+}
+
+impl Default for TokenSource {
+	fn default() -> Self {
+		Self::Header(AUTHORIZATION)
+	}
+}
+
+/// Return the subprotocol following the given marker in `Sec-WebSocket-Protocol`
+fn subprotocol(req: &HttpRequest, marker: &str) -> Option<String> {
+	let mut protocols = req
+		.headers()
+		.get_all(SEC_WEBSOCKET_PROTOCOL)
+		.filter_map(|value| value.to_str().ok())
+		.flat_map(|value| value.split(','))
+		.map(str::trim);
+	protocols.find(|protocol| protocol == &marker)?;
+	protocols.next().map(|token| token.to_owned())
+}
+
+impl TokenSource {
+	/// Extract the token from the request (the first one if there are several)
+	pub(crate) fn token(&self, req: &HttpRequest) -> Option<String> {
+		self.tokens(req).into_iter().next()
+	}
+
+	/// Extract the tokens from the request in order: the first 3 `Bearer` credentials of all the
+	/// headers, which can also hold comma separated lists, or the single token of the other
+	/// sources. The extra credentials are ignored so that a request can't cost many validations
+	pub(crate) fn tokens(&self, req: &HttpRequest) -> Vec<String> {
+		match self {
+			Self::Header(name) => req
+				.headers()
+				.get_all(name)
+				.filter_map(|value| value.to_str().ok())
+				.flat_map(|value| value.split(','))
+				.filter_map(|value| chain::credentials(value.trim(), "Bearer"))
+				.take(MAX_CANDIDATES)
+				.map(|token| token.to_owned())
+				.collect(),
+			Self::Cookie(name) => req
+				.cookie(name)
+				.map(|cookie| cookie.value().to_owned())
+				.into_iter()
+				.collect(),
+			Self::WebSocket(marker) => subprotocol(req, marker).into_iter().collect(),
+		}
+	}
+
+	/// Add the accepted subprotocol to the response of a websocket upgrade if necessary
+	fn echo<B>(&self, res: &mut ServiceResponse<B>) {
+		if let Self::WebSocket(marker) = self {
+			if res.status() == StatusCode::SWITCHING_PROTOCOLS
+				&& !res.headers().contains_key(SEC_WEBSOCKET_PROTOCOL)
+			{
+				if let Ok(value) = HeaderValue::from_str(marker) {
+					res.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+				}
+			}
+		}
+	}
+
+	/// Hand a refreshed token to the client: in the cookie the token is read from, or in a
+	/// `X-Refreshed-Token` header for the other sources
+	fn renew<B>(&self, res: &mut ServiceResponse<B>, token: &str) {
+		match self {
+			Self::Cookie(name) => {
+				let cookie = Cookie::build(name.as_str(), token)
+					.path("/")
+					.secure(true)
+					.http_only(true)
+					.same_site(SameSite::Strict)
+					.finish();
+				if let Err(e) = res.response_mut().add_cookie(&cookie) {
+					tracing::warn!("Failed to set the refreshed token cookie: {}", e);
+				}
+			}
+			_ => {
+				if let Ok(value) = HeaderValue::from_str(token) {
+					res.headers_mut()
+						.insert(HeaderName::from_static("x-refreshed-token"), value);
+				}
+			}
+		}
+	}
+}
+
+/// Callback exchanging a token close to its expiration for a new one (eg. with an OAuth refresh
+/// grant using a refresh token cookie of the request)
+pub type RefreshFn =
+	Arc<dyn Fn(HttpRequest, String) -> LocalBoxFuture<'static, AuthResult<String>> + Send + Sync>;
+
+/// Callback extracting from the request the value a claim must be equal to
+pub type BindingFn = Arc<dyn Fn(&ServiceRequest) -> Option<String> + Send + Sync>;
+
+/// Part of the request a claim must be equal to
+#[derive(Clone)]
+pub enum Binding {
+	/// Segment captured by the route pattern (`{project}`). Only available when the middleware
+	/// wraps a resource or a scope whose pattern defines it
+	Path(String),
+	/// Query string parameter
+	Query(String),
+	/// Cookie value (eg. the nonce stored before redirecting to the identity provider)
+	Cookie(String),
+	/// Value returned by a callback (eg. a nonce kept in a server side session)
+	Custom(BindingFn),
+}
+
+impl fmt::Debug for Binding {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Path(name) => f.debug_tuple("Path").field(name).finish(),
+			Self::Query(name) => f.debug_tuple("Query").field(name).finish(),
+			Self::Cookie(name) => f.debug_tuple("Cookie").field(name).finish(),
+			Self::Custom(_) => f.write_str("Custom"),
+		}
+	}
+}
+
+impl Binding {
+	/// Extract the bound value from the request
+	fn value(&self, req: &ServiceRequest) -> Option<String> {
+		match self {
+			Self::Path(name) => req.match_info().get(name).map(|value| value.to_owned()),
+			Self::Query(name) => {
+				serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+					.ok()?
+					.into_iter()
+					.find(|(key, _)| key == name)
+					.map(|(_, value)| value)
+			}
+			Self::Cookie(name) => req
+				.request()
+				.cookie(name)
+				.map(|cookie| cookie.value().to_owned()),
+			Self::Custom(value) => value(req),
+		}
+	}
+}
+
+/// When the keys are reloaded from the jwks endpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RefreshPolicy {
+	/// Keep the keys loaded at startup
+	#[default]
+	Never,
+	/// Reload the keys in the background when they are older than the given duration
+	Interval(Duration),
+}
+
+/// Remaining lifetime of the validated token, inserted in the request extensions when it has an
+/// `exp` claim (extract it with `web::ReqData<ExpiresIn>`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExpiresIn(pub Duration);
+
+/// Options shared by all the middlewares instanciated by a JwtAuth factory
+struct Options {
+	source: TokenSource,
+	error_format: ErrorFormat,
+	error_template: Option<ErrorTemplate>,
+	error_handler: Option<ErrorHandler>,
+	realm: Option<String>,
+	login: Option<LoginRedirect>,
+	skip_paths: Vec<String>,
+	bypass: NetworkBypass,
+	bindings: Vec<(String, Binding)>,
+	acr: Option<(Vec<String>, usize)>,
+	amr: Vec<String>,
+	roles: Vec<String>,
+	userinfo: Option<Userinfo>,
+	#[cfg(feature = "casbin")]
+	casbin: Option<Casbin>,
+	#[cfg(feature = "cedar")]
+	cedar: Option<Cedar>,
+	refresh: RefreshPolicy,
+	limiter: Option<FailureLimiter>,
+	redaction: Redaction,
+	span_redaction: Redaction,
+	salt: FingerprintSalt,
+	identity: IdentityHeaders,
+	debug: bool,
+	expires_in_header: bool,
+	renewal: Option<(Duration, RefreshFn)>,
+	#[cfg(feature = "metrics")]
+	metrics: Option<Metrics>,
+	// last time the keys were loaded
+	refreshed: Mutex<Instant>,
+	// configurations selected by request host
+	tenants: HashMap<String, Arc<Jwt>>,
+	// configurations selected by token issuer
+	issuers: HashMap<String, Arc<Jwt>>,
+	// accepted issuers (any if empty)
+	allowed_issuers: Vec<String>,
+	// claims required from the tokens of an issuer
+	issuer_claims: HashMap<String, ClaimOverride>,
+}
+
+/// Builder for JwtAuth
+pub struct JwtAuthBuilder {
+	jwt: Jwt,
+	tenants: HashMap<String, Arc<Jwt>>,
+	issuers: HashMap<String, Arc<Jwt>>,
+	allowed_issuers: Vec<String>,
+	issuer_claims: HashMap<String, ClaimOverride>,
+	source: TokenSource,
+	error_format: ErrorFormat,
+	error_template: Option<ErrorTemplate>,
+	error_handler: Option<ErrorHandler>,
+	realm: Option<String>,
+	login: Option<LoginRedirect>,
+	skip_paths: Vec<String>,
+	bypass: NetworkBypass,
+	bindings: Vec<(String, Binding)>,
+	acr: Option<(Vec<String>, usize)>,
+	amr: Vec<String>,
+	roles: Vec<String>,
+	userinfo: Option<Userinfo>,
+	#[cfg(feature = "casbin")]
+	casbin: Option<Casbin>,
+	#[cfg(feature = "cedar")]
+	cedar: Option<Cedar>,
+	refresh: RefreshPolicy,
+	limiter: Option<FailureLimiter>,
+	redaction: Redaction,
+	span_redaction: Redaction,
+	salt: FingerprintSalt,
+	identity: IdentityHeaders,
+	debug: bool,
+	expires_in_header: bool,
+	renewal: Option<(Duration, RefreshFn)>,
+	#[cfg(feature = "metrics")]
+	metrics: Option<Metrics>,
+}
+
+impl JwtAuthBuilder {
+	/// Read the token from the given source (`Authorization: Bearer` by default)
+	pub fn source(mut self, source: TokenSource) -> Self {
+		self.source = source;
+		self
+	}
+
+	/// Read the token from the `Bearer` credentials of the given header
+	pub fn header(self, name: HeaderName) -> Self {
+		self.source(TokenSource::Header(name))
+	}
+
+	/// Read the token from the given cookie
+	pub fn cookie(self, name: &str) -> Self {
+		self.source(TokenSource::Cookie(name.to_owned()))
+	}
+
+	/// Allowed clock skew in seconds when checking the token expiration
+	pub fn leeway(mut self, leeway: u64) -> Self {
+		self.jwt.leeway = leeway;
+		self
+	}
+
+	/// Set the format of the rejection body (ignored when an error handler is set)
+	pub fn error_format(mut self, format: ErrorFormat) -> Self {
+		self.error_format = format;
+		self
+	}
+
+	/// Render the rejection bodies from the given template instead of the error format
+	/// (ignored when an error handler is set)
+	pub fn error_template(mut self, template: ErrorTemplate) -> Self {
+		self.error_template = Some(template);
+		self
+	}
+
+	/// Use a custom callback to render rejections instead of the default response
+	pub fn error_handler<F>(mut self, handler: F) -> Self
+	where
+		F: Fn(&AuthError, &ServiceRequest) -> HttpResponse + Send + Sync + 'static,
+	{
+		self.error_handler = Some(Arc::new(handler));
+		self
+	}
+
+	/// Name the protected realm in challenges and error bodies
+	pub fn realm(mut self, realm: &str) -> Self {
+		self.realm = Some(realm.to_owned());
+		self
+	}
+
+	/// Include the precise failure (missing claim, expected and found values, expiration
+	/// time...) in the rejection body and in a `X-Auth-Debug` header. For development only, as
+	/// it tells clients why their tokens are rejected
+	pub fn debug(mut self) -> Self {
+		self.debug = true;
+		self
+	}
+
+	/// Send the remaining lifetime of the token in seconds in a `X-Token-Expires-In` response
+	/// header, so that clients can refresh it before being rejected
+	pub fn expires_in_header(mut self) -> Self {
+		self.expires_in_header = true;
+		self
+	}
+
+	/// Call the refresher when the validated token expires within the given duration, and hand
+	/// the new token to the client with the response (in the cookie the token is read from, or
+	/// in a `X-Refreshed-Token` header), keeping browser sessions alive. The refresh runs once
+	/// the request is served with the current token, and the response waits for it whatever its
+	/// outcome
+	pub fn refresh_before_expiry<F, Fut>(mut self, within: Duration, refresher: F) -> Self
+	where
+		F: Fn(HttpRequest, String) -> Fut + Send + Sync + 'static,
+		Fut: Future<Output = AuthResult<String>> + 'static,
+	{
+		let refresher: RefreshFn = Arc::new(move |req, token| Box::pin(refresher(req, token)));
+		self.renewal = Some((within, refresher));
+		self
+	}
+
+	/// Redirect browsers to a login page instead of answering 401
+	pub fn login_redirect(mut self, login: LoginRedirect) -> Self {
+		self.login = Some(login);
+		self
+	}
+
+	/// Let requests through without token when their path is the given prefix or one of its
+	/// sub-paths (`/public` skips `/public/logo.png` but not `/public-admin`)
+	pub fn skip_path(mut self, prefix: &str) -> Self {
+		self.skip_paths.push(prefix.to_owned());
+		self
+	}
+
+	/// Let requests coming from trusted networks through without token
+	pub fn bypass(mut self, bypass: NetworkBypass) -> Self {
+		self.bypass = bypass;
+		self
+	}
+
+	/// Require the claim `key` to be present in the token with the given value
+	pub fn claim(self, key: &str, value: &str) -> Self {
+		self.claim_value(key, value.into())
+	}
+
+	/// Require the claim `key` to be present in the token and equal to the given json value
+	pub fn claim_value(mut self, key: &str, value: Value) -> Self {
+		self.jwt.claims.push((key.to_owned(), value));
+		self
+	}
+
+	/// Require the claim `key` to be equal to a part of the request
+	pub fn bind_claim(mut self, key: &str, binding: Binding) -> Self {
+		self.bindings.push((key.to_owned(), binding));
+		self
+	}
+
+	/// Require the `nonce` claim of ID tokens to be equal to the nonce sent to the identity
+	/// provider, to protect OIDC callback endpoints against replayed tokens
+	pub fn nonce(self, binding: Binding) -> Self {
+		self.bind_claim("nonce", binding)
+	}
+
+	/// Require the `acr` claim to be at least the given class, given the classes of the identity
+	/// provider from the weakest to the strongest (eg. `["bronze", "silver", "gold"]`)
+	pub fn min_acr(mut self, classes: &[&str], minimum: &str) -> Self {
+		let classes = classes
+			.iter()
+			.map(|class| class.to_string())
+			.collect::<Vec<_>>();
+		// an unknown minimum can't be reached
+		let minimum = classes
+			.iter()
+			.position(|class| class == minimum)
+			.unwrap_or(classes.len());
+		self.acr = Some((classes, minimum));
+		self
+	}
+
+	/// Require the `amr` claim to contain the given authentication method (eg. `mfa`). Can be
+	/// called several times
+	pub fn require_amr(mut self, method: &str) -> Self {
+		self.amr.push(method.to_owned());
+		self
+	}
+
+	/// Require the given Keycloak role, either a realm role or a client role written
+	/// `<client>:<role>`. Can be called several times
+	pub fn require_role(mut self, role: &str) -> Self {
+		self.roles.push(role.to_owned());
+		self
+	}
+
+	/// Merge the claims of the OIDC userinfo endpoint into the validated claims (the ones seen
+	/// by the identity headers and the AuthContext)
+	pub fn userinfo(mut self, userinfo: Userinfo) -> Self {
+		self.userinfo = Some(userinfo);
+		self
+	}
+
+	/// Check the validated requests against a casbin policy (rejected with 403 when denied)
+	#[cfg(feature = "casbin")]
+	pub fn casbin(mut self, casbin: Casbin) -> Self {
+		self.casbin = Some(casbin);
+		self
+	}
+
+	/// Evaluate the validated requests against Cedar policies (rejected with 403 when denied)
+	#[cfg(feature = "cedar")]
+	pub fn cedar(mut self, cedar: Cedar) -> Self {
+		self.cedar = Some(cedar);
+		self
+	}
+
+	/// Set when the keys are reloaded from the jwks endpoint
+	pub fn refresh(mut self, refresh: RefreshPolicy) -> Self {
+		self.refresh = refresh;
+		self
+	}
+
+	/// Temporarily block clients presenting too many invalid tokens. Clients are identified by
+	/// the address resolved by the network bypass, which only trusts the forwarding headers of
+	/// its proxies
+	pub fn rate_limit(mut self, limiter: FailureLimiter) -> Self {
+		self.limiter = Some(limiter);
+		self
+	}
+
+	/// Set how the token subject appears in audit events (hidden by default)
+	pub fn redaction(mut self, redaction: Redaction) -> Self {
+		self.redaction = redaction;
+		self
+	}
+
+	/// Set how the token subject appears in the `authenticate` tracing span (hidden by default)
+	pub fn span_redaction(mut self, redaction: Redaction) -> Self {
+		self.span_redaction = redaction;
+		self
+	}
+
+	/// Salt of the token fingerprints logged with the rejections (random by default). Set the
+	/// same salt on all the replicas to correlate the fingerprints across them
+	pub fn fingerprint_salt(mut self, salt: &str) -> Self {
+		self.salt = FingerprintSalt::new(salt);
+		self
+	}
+
+	/// Pass identity headers derived from the claims to the handlers (client supplied values are
+	/// always removed)
+	pub fn identity_headers(mut self, identity: IdentityHeaders) -> Self {
+		self.identity = identity;
+		self
+	}
+
+	/// Record authentication metrics
+	#[cfg(feature = "metrics")]
+	pub fn metrics(mut self, metrics: Metrics) -> Self {
+		self.metrics = Some(metrics);
+		self
+	}
+
+	/// Validate the tokens of requests for the given host (without port) with a dedicated Jwt
+	/// (keys and claims) instead of the default one. Leeway and claims set on the builder only
+	/// apply to the default Jwt
+	pub fn tenant(mut self, host: &str, jwt: Jwt) -> Self {
+		self.tenants.insert(host.to_lowercase(), Arc::new(jwt));
+		self
+	}
+
+	/// Only accept the tokens of the given issuers. The unverified `iss` claim is checked before
+	/// the signature so that tokens of other issuers are rejected cheaply
+	pub fn allow_issuers(mut self, issuers: &[&str]) -> Self {
+		self.allowed_issuers
+			.extend(issuers.iter().map(|issuer| issuer.to_string()));
+		self
+	}
+
+	/// Validate the tokens issued by `iss` with a dedicated Jwt (keys and claims). Once an
+	/// issuer is registered, the tokens of unknown issuers are rejected before their signature
+	/// is checked
+	pub fn issuer(mut self, iss: &str, jwt: Jwt) -> Self {
+		self.allowed_issuers.push(iss.to_owned());
+		self.issuers.insert(iss.to_owned(), Arc::new(jwt));
+		self
+	}
+
+	/// Require the given claims on top of the other ones from the tokens issued by `iss` (eg.
+	/// `project_path` for GitLab tokens), when several issuers are trusted
+	pub fn issuer_claims(mut self, iss: &str, claims: ClaimOverride) -> Self {
+		self.issuer_claims.insert(iss.to_owned(), claims);
+		self
+	}
+
+	/// Construct the JwtAuth middleware factory
+	pub fn build(self) -> JwtAuth {
+		JwtAuth {
+			jwt: Arc::new(self.jwt),
+			options: Arc::new(Options {
+				source: self.source,
+				error_format: self.error_format,
+				error_template: self.error_template,
+				error_handler: self.error_handler,
+				realm: self.realm,
+				login: self.login,
+				skip_paths: self.skip_paths,
+				bypass: self.bypass,
+				bindings: self.bindings,
+				acr: self.acr,
+				amr: self.amr,
+				roles: self.roles,
+				userinfo: self.userinfo,
+				#[cfg(feature = "casbin")]
+				casbin: self.casbin,
+				#[cfg(feature = "cedar")]
+				cedar: self.cedar,
+				refresh: self.refresh,
+				limiter: self.limiter,
+				redaction: self.redaction,
+				span_redaction: self.span_redaction,
+				salt: self.salt,
+				identity: self.identity,
+				debug: self.debug,
+				expires_in_header: self.expires_in_header,
+				renewal: self.renewal,
+				#[cfg(feature = "metrics")]
+				metrics: self.metrics,
+				refreshed: Mutex::new(Instant::now()),
+				tenants: self.tenants,
+				issuers: self.issuers,
+				allowed_issuers: self.allowed_issuers,
+				issuer_claims: self.issuer_claims,
+			}),
+		}
+	}
+}
 
 #[derive(Clone)]
-/// Middleware factory than instanciate JwtAuthMiddleware
-pub struct JwtAuth(Rc<Jwt>);
+/// Middleware factory than instanciate JwtAuthMiddleware (Send + Sync so that it can be
+/// constructed once and shared between workers)
+pub struct JwtAuth {
+	jwt: Arc<Jwt>,
+	options: Arc<Options>,
+}
 
 impl JwtAuth {
 	/// Construct a JwtAuth instance that forwards a Jwt struct to all its middleware
 	pub fn new(jwt: Jwt) -> Self {
-		Self(Rc::new(jwt))
+		Self::builder(jwt).build()
+	}
+
+	/// Jwt validating the tokens, whose clones share the keys (eg. to validate the gRPC requests
+	/// of the same service)
+	pub fn jwt(&self) -> &Jwt {
+		&self.jwt
+	}
+
+	/// Start building a JwtAuth instance with non default options
+	pub fn builder(jwt: Jwt) -> JwtAuthBuilder {
+		JwtAuthBuilder {
+			jwt,
+			tenants: HashMap::new(),
+			issuers: HashMap::new(),
+			allowed_issuers: Vec::new(),
+			issuer_claims: HashMap::new(),
+			source: TokenSource::default(),
+			error_format: ErrorFormat::default(),
+			error_template: None,
+			error_handler: None,
+			realm: None,
+			login: None,
+			skip_paths: Vec::new(),
+			bypass: NetworkBypass::default(),
+			bindings: Vec::new(),
+			acr: None,
+			amr: Vec::new(),
+			roles: Vec::new(),
+			userinfo: None,
+			#[cfg(feature = "casbin")]
+			casbin: None,
+			#[cfg(feature = "cedar")]
+			cedar: None,
+			refresh: RefreshPolicy::default(),
+			limiter: None,
+			redaction: Redaction::default(),
+			span_redaction: Redaction::Hidden,
+			salt: FingerprintSalt::default(),
+			identity: IdentityHeaders::default(),
+			debug: false,
+			expires_in_header: false,
+			renewal: None,
+			#[cfg(feature = "metrics")]
+			metrics: None,
+		}
+	}
+
+	/// Start building a JwtAuth instance from a configuration block (the keys are fetched from
+	/// the jwks endpoint)
+	pub async fn builder_from_config(config: &AuthConfig) -> AuthResult<JwtAuthBuilder> {
+		let mut jwt = Jwt::unloaded(&config.jwks, config.claims.clone());
+		if config.allow_insecure_jwks {
+			jwt = jwt.allow_insecure_jwks();
+		}
+		for pin in &config.pins {
+			jwt = jwt.pin(pin);
+		}
+		jwt = jwt.key_policy(config.key_policy.clone());
+		if config.allow_missing_kid {
+			jwt = jwt.allow_missing_kid();
+		}
+		if let Some(skew) = config.iat_skew {
+			jwt = jwt.iat_skew(skew);
+		}
+		if let Some(len) = config.max_token_len {
+			jwt = jwt.max_token_len(len);
+		}
+		if let Some(template) = &config.issuer_template {
+			jwt = jwt.issuer_template(template);
+		}
+		if config.ignore_kid_case {
+			jwt = jwt.ignore_kid_case();
+		}
+		jwt.set_keys().await?;
+		let mut builder = Self::builder(jwt)
+			.source(config.source.token_source()?)
+			.leeway(config.leeway)
+			.refresh(config.refresh_policy())
+			.error_format(config.error_format);
+		if let Some(realm) = &config.realm {
+			builder = builder.realm(realm);
+		}
+		if let Some(template) = &config.error_template {
+			builder = builder.error_template(template.clone());
+		}
+		if config.debug {
+			builder = builder.debug();
+		}
+		if config.expires_in_header {
+			builder = builder.expires_in_header();
+		}
+		for (iss, claims) in &config.issuer_claims {
+			builder = builder.issuer_claims(iss, claims.clone());
+		}
+		Ok(builder)
+	}
+
+	/// Construct a JwtAuth instance from a configuration block
+	pub async fn from_config(config: &AuthConfig) -> AuthResult<Self> {
+		Ok(Self::builder_from_config(config).await?.build())
+	}
+}
+
+impl JwtAuth {
+	/// Convert an authentication error to an actix error using the error handler if any
+	pub(crate) fn reject(&self, e: AuthError, req: &ServiceRequest) -> Error {
+		#[cfg(feature = "otel")]
+		otel::rejected(&e);
+		#[cfg(feature = "metrics")]
+		if let Some(metrics) = &self.options.metrics {
+			metrics.failure(e.reason());
+		}
+		let res = match (&self.options.error_handler, &self.options.login) {
+			(Some(handler), _) => handler(&e, req),
+			(None, Some(login))
+				if reject::status(&e) == StatusCode::UNAUTHORIZED
+					&& login.applies(req.request()) =>
+			{
+				login.response(req.request())
+			}
+			(None, _) if self.options.debug => {
+				let token = self.options.source.token(req.request());
+				// the time of the clock that rejected the token
+				let now = token
+					.as_deref()
+					.and_then(|token| self.select(req, token).ok())
+					.unwrap_or(&self.jwt)
+					.now();
+				reject::debug_response(
+					&e,
+					self.options.error_format,
+					self.options.realm.as_deref(),
+					&reject::diagnostic(&e, token.as_deref(), now),
+				)
+			}
+			(None, _) => match &self.options.error_template {
+				Some(template) => {
+					template.response(&e, self.options.realm.as_deref(), req.request())
+				}
+				None => {
+					reject::response(&e, self.options.error_format, self.options.realm.as_deref())
+				}
+			},
+		};
+		InternalError::from_response(e, res).into()
+	}
+
+	/// Refresher to call with the token of the request when the token is about to expire
+	fn renewal(&self, req: &ServiceRequest) -> Option<(RefreshFn, String)> {
+		let (within, refresher) = self.options.renewal.as_ref()?;
+		let extensions = req.extensions();
+		let ExpiresIn(ttl) = extensions.get::<ExpiresIn>()?;
+		let SubjectToken(token) = extensions.get::<SubjectToken>()?;
+		(ttl < within).then(|| (refresher.clone(), token.clone()))
+	}
+
+	/// Reload the keys in the background if they are too old
+	fn refresh(&self) {
+		if let RefreshPolicy::Interval(interval) = self.options.refresh {
+			let mut refreshed = self.options.refreshed.lock().unwrap();
+			if refreshed.elapsed() >= interval {
+				*refreshed = Instant::now();
+				let jwts = std::iter::once(&self.jwt)
+					.chain(self.options.tenants.values())
+					.chain(self.options.issuers.values())
+					.cloned()
+					.collect::<Vec<_>>();
+				#[cfg(feature = "metrics")]
+				let metrics = self.options.metrics.clone();
+				rt::spawn(async move {
+					for jwt in jwts {
+						let _res = jwt.set_keys().await;
+						#[cfg(feature = "metrics")]
+						if let Some(metrics) = &metrics {
+							metrics.refresh(_res.is_ok());
+						}
+					}
+				});
+			}
+		}
+	}
+
+	/// Return the Jwt configured for the host of the request
+	fn tenant(&self, req: &ServiceRequest) -> &Jwt {
+		if self.options.tenants.is_empty() {
+			return &self.jwt;
+		}
+		// use the request host (not the forwarded one that can be set by clients)
+		req.uri()
+			.host()
+			.or_else(|| req.headers().get(HOST).and_then(|host| host.to_str().ok()))
+			.map(|host| match host.rsplit_once(':') {
+				Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+				_ => host,
+			})
+			.and_then(|host| self.options.tenants.get(&host.to_lowercase()))
+			.unwrap_or(&self.jwt)
+	}
+
+	/// Return the Jwt validating the token: the one of its issuer if any, or the one configured
+	/// for the host of the request. Tokens of issuers not allowed are rejected
+	fn select(&self, req: &ServiceRequest, token: &str) -> Result<&Jwt, AuthError> {
+		if self.options.allowed_issuers.is_empty() {
+			return Ok(self.tenant(req));
+		}
+		self.jwt.check_shape(token)?;
+		// the signature is not checked yet, the claim is only used to reject and route the token
+		let iss = data::unverified_issuer(token).ok_or(AuthError::InvalidToken)?;
+		if !self.options.allowed_issuers.contains(&iss) {
+			return Err(AuthError::UntrustedIssuer(iss));
+		}
+		Ok(self
+			.options
+			.issuers
+			.get(&iss)
+			.map(|jwt| jwt.as_ref())
+			.unwrap_or_else(|| self.tenant(req)))
+	}
+
+	/// Check that the claims match the bound parts of the request
+	fn check_bindings(&self, req: &ServiceRequest, claims: &Value) -> Result<(), AuthError> {
+		for (key, binding) in &self.options.bindings {
+			let claim = claims
+				.get(key)
+				.ok_or_else(|| AuthError::ClaimNotFound(key.to_owned()))?;
+			let value = binding.value(req);
+			let matches = match (claim, &value) {
+				(Value::String(claim), Some(value)) => claim == value,
+				(claim, Some(value)) => claim.to_string() == *value,
+				(_, None) => false,
+			};
+			if !matches {
+				return Err(AuthError::Claim(
+					key.to_owned(),
+					value.unwrap_or_default(),
+					claim.to_string(),
+				));
+			}
+		}
+		Ok(())
+	}
+
+	/// Check that the token was obtained with a strong enough authentication
+	fn check_authentication(&self, claims: &Value) -> Result<(), AuthError> {
+		if let Some((classes, minimum)) = &self.options.acr {
+			let acr = claims
+				.get("acr")
+				.and_then(Value::as_str)
+				.unwrap_or_default();
+			match classes.iter().position(|class| class == acr) {
+				Some(level) if level >= *minimum => (),
+				_ => return Err(AuthError::StepUp(format!("acr {} is too weak", acr))),
+			}
+		}
+		let amr = claims.get("amr").and_then(Value::as_array);
+		for method in &self.options.amr {
+			let found = amr.is_some_and(|amr| amr.iter().any(|m| m == method.as_str()));
+			if !found {
+				return Err(AuthError::StepUp(format!("amr {} is missing", method)));
+			}
+		}
+		Ok(())
+	}
+
+	/// Check that the Keycloak roles of the token include the required ones
+	fn check_roles(&self, claims: &Value) -> Result<(), AuthError> {
+		if self.options.roles.is_empty() {
+			return Ok(());
+		}
+		let roles = Roles::keycloak(claims);
+		match self.options.roles.iter().find(|role| !roles.contains(role)) {
+			Some(role) => Err(AuthError::Claim(
+				"roles".to_owned(),
+				role.to_owned(),
+				roles.iter().collect::<Vec<_>>().join(","),
+			)),
+			None => Ok(()),
+		}
+	}
+
+	/// Check the claims required from the tokens of the issuer of the validated claims
+	fn check_issuer_claims(&self, claims: &Value) -> Result<(), AuthError> {
+		let required = claims
+			.get("iss")
+			.and_then(Value::as_str)
+			.and_then(|iss| self.options.issuer_claims.get(iss));
+		match required {
+			Some(required) => data::check_claims(required.claims(), claims),
+			None => Ok(()),
+		}
+	}
+
+	/// Check that the request is allowed to reach the protected service, in an `authenticate`
+	/// span carrying the outcome and the token fields
+	pub(crate) async fn authenticate(
+		&self,
+		req: &mut ServiceRequest,
+	) -> Result<AuthContext, AuthError> {
+		// the otel attributes belong to the request span
+		let parent = tracing::Span::current();
+		let span = tracing::info_span!(
+			"authenticate",
+			outcome = tracing::field::Empty,
+			kid = tracing::field::Empty,
+			alg = tracing::field::Empty,
+			iss = tracing::field::Empty,
+			sub = tracing::field::Empty,
+		);
+		self.authenticate_request(req, &parent)
+			.instrument(span)
+			.await
+	}
+
+	/// Check that the request is allowed to reach the protected service
+	async fn authenticate_request(
+		&self,
+		req: &mut ServiceRequest,
+		#[cfg_attr(not(feature = "otel"), allow(unused_variables))] parent: &tracing::Span,
+	) -> Result<AuthContext, AuthError> {
+		self.options.identity.strip(req.headers_mut());
+		let path = req.path();
+		if self.options.skip_paths.iter().any(|p| skipped(path, p))
+			|| self.options.bypass.bypass(req)
+		{
+			audit::record("anonymous", None, None, self.options.span_redaction);
+			return Ok(AuthContext::Anonymous);
+		}
+		let limiter = self
+			.options
+			.limiter
+			.as_ref()
+			.zip(self.options.bypass.client_ip(req));
+		if let Some(wait) = limiter.and_then(|(limiter, ip)| limiter.blocked(ip)) {
+			let e = AuthError::TooManyFailures(wait.as_secs().max(1));
+			audit::record("rejected", None, None, self.options.span_redaction);
+			audit::rejected(req, None, &e, &self.options.salt, self.options.redaction);
+			return Err(e);
+		}
+		self.refresh();
+		let tokens = self.options.source.tokens(req.request());
+		#[cfg(feature = "metrics")]
+		let start = Instant::now();
+		// remaining lifetime of the valid token, computed by the Jwt that validated it
+		let expires_in = Cell::new(None);
+		let validate = |jwt: &str| {
+			let tenant = self.select(req, jwt)?;
+			let claims = req.app_data::<ClaimOverride>().or_else(|| {
+				req.app_data::<web::Data<ClaimOverride>>()
+					.map(|data| data.as_ref())
+			});
+			match claims {
+				Some(claims) => tenant.validate_jwt_with(jwt, &claims.merge(&tenant.claims)),
+				None => tenant.validate_jwt(jwt),
+			}
+			.and_then(|tokendata| {
+				self.check_issuer_claims(&tokendata.claims)?;
+				self.check_bindings(req, &tokendata.claims)?;
+				self.check_authentication(&tokendata.claims)?;
+				self.check_roles(&tokendata.claims)?;
+				expires_in.set(tenant.expires_in(&tokendata.claims));
+				Ok(tokendata)
+			})
+		};
+		// the first valid token is accepted, the error of the first one is reported otherwise
+		let (mut token, mut res) = (None, Err(AuthError::NoBearer));
+		for candidate in tokens {
+			let candidate_res = validate(&candidate);
+			let valid = candidate_res.is_ok();
+			if token.is_none() || valid {
+				token = Some(candidate);
+				res = candidate_res;
+			}
+			if valid {
+				break;
+			}
+		}
+		if let (Some(jwt), Err(AuthError::KeyNotFound(_))) = (token.as_deref(), &res) {
+			// the provider may have rotated its keys
+			let refreshed = match self.select(req, jwt) {
+				Ok(tenant) => tenant.refresh_for(jwt).await.is_ok(),
+				Err(_) => false,
+			};
+			if refreshed {
+				res = validate(jwt);
+			}
+		}
+		if let (Some(userinfo), Some(jwt)) = (&self.options.userinfo, token.as_deref()) {
+			if let Ok(mut tokendata) = res {
+				res = userinfo
+					.enrich(jwt, &mut tokendata.claims)
+					.await
+					.map(|_| tokendata);
+			}
+		}
+		#[cfg(feature = "casbin")]
+		if let Some(casbin) = &self.options.casbin {
+			if let Ok(tokendata) = res {
+				res = casbin
+					.check(req, &tokendata.claims)
+					.await
+					.map(|_| tokendata);
+			}
+		}
+		#[cfg(feature = "cedar")]
+		if let Some(cedar) = &self.options.cedar {
+			if let Ok(tokendata) = res {
+				res = cedar.check(req, &tokendata.claims).map(|_| tokendata);
+			}
+		}
+		#[cfg(feature = "metrics")]
+		if let Some(metrics) = &self.options.metrics {
+			metrics.latency(start.elapsed());
+			if res.is_ok() {
+				metrics.success();
+			}
+		}
+		if let Some((limiter, ip)) = limiter {
+			match &res {
+				Ok(_) => limiter.success(ip),
+				Err(AuthError::NoBearer | AuthError::KeysUnavailable) => (),
+				// not the client's fault
+				Err(AuthError::Userinfo(_)) => (),
+				Err(_) => limiter.failure(ip),
+			}
+		}
+		match res {
+			Ok(tokendata) => {
+				audit::record(
+					"accepted",
+					Some(&tokendata.header),
+					Some(&tokendata.claims),
+					self.options.span_redaction,
+				);
+				audit::accepted(req, &tokendata, self.options.redaction);
+				#[cfg(feature = "otel")]
+				parent.in_scope(|| otel::accepted(&tokendata));
+				self.options
+					.identity
+					.inject(req.headers_mut(), &tokendata.claims);
+				if let Some(token) = token {
+					req.extensions_mut().insert(SubjectToken(token));
+				}
+				if let Some(ttl) = expires_in.get() {
+					req.extensions_mut().insert(ExpiresIn(ttl));
+				}
+				Ok(AuthContext::Jwt(tokendata.claims))
+			}
+			Err(e) => {
+				let header = token
+					.as_deref()
+					.and_then(|token| jwt::decode_header(token).ok());
+				audit::record(
+					"rejected",
+					header.as_ref(),
+					None,
+					self.options.span_redaction,
+				);
+				audit::rejected(
+					req,
+					token.as_deref(),
+					&e,
+					&self.options.salt,
+					self.options.redaction,
+				);
+				Err(e)
+			}
+		}
+	}
+}
+
+impl Authenticator for JwtAuth {
+	fn authenticate<'a>(
+		&'a self,
+		req: &'a mut ServiceRequest,
+	) -> LocalBoxFuture<'a, Result<AuthContext, AuthError>> {
+		Box::pin(JwtAuth::authenticate(self, req))
+	}
+
+	fn reject(&self, e: AuthError, req: &ServiceRequest) -> Error {
+		JwtAuth::reject(self, e, req)
 	}
 }
 
@@ -25,7 +1095,7 @@ impl JwtAuth {
 // `B` - type of response's body
 impl<S, B> Transform<S, ServiceRequest> for JwtAuth
 where
-	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
 	S::Future: 'static,
 	B: 'static,
 {
@@ -37,45 +1107,83 @@ where
 
 	fn new_transform(&self, service: S) -> Self::Future {
 		ok(JwtAuthMiddleware {
-			service,
-			jwt: self.0.clone(),
+			service: Rc::new(service),
+			auth: self.clone(),
 		})
 	}
 }
 
 pub struct JwtAuthMiddleware<S> {
-	service: S,
-	jwt: Rc<Jwt>,
+	service: Rc<S>,
+	auth: JwtAuth,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtAuthMiddleware<S>
 where
-	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
 	S::Future: 'static,
+	B: 'static,
 {
 	type Response = ServiceResponse<B>;
 	type Error = Error;
-	type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
 	forward_ready!(service);
 
-	fn call(&self, req: ServiceRequest) -> Self::Future {
-		if let Some(jwt) = req
-			.headers()
-			.get(AUTHORIZATION)
-			.and_then(|token| token.to_str().ok())
-			.and_then(|token| token.find("Bearer ").map(|_| &token[7..]))
-		{
-			self.jwt
-				.validate_jwt(jwt)
-				.map(|_| Either::left(self.service.call(req)))
-				.unwrap_or_else(|e| {
-					Either::right(err(ErrorUnauthorized(format!("Not authorized - {}", e))))
-				})
-		} else {
-			Either::right(err(ErrorUnauthorized(
-				"Not authorized - Missing bearer token",
-			)))
-		}
+	fn call(&self, mut req: ServiceRequest) -> Self::Future {
+		let service = self.service.clone();
+		let auth = self.auth.clone();
+		Box::pin(async move {
+			match auth.authenticate(&mut req).await {
+				Ok(context) => {
+					req.extensions_mut().insert(context);
+					let expires_in = req
+						.extensions()
+						.get::<ExpiresIn>()
+						.filter(|_| auth.options.expires_in_header)
+						.copied();
+					let renewal = auth.renewal(&req);
+					let mut res = service.call(req).await?;
+					auth.options.source.echo(&mut res);
+					// the request can only be shared once the inner services are done with it
+					if let Some((refresher, token)) = renewal {
+						match refresher(res.request().clone(), token).await {
+							Ok(token) => auth.options.source.renew(&mut res, &token),
+							Err(e) => tracing::warn!("Failed to refresh the token: {}", e),
+						}
+					}
+					if let Some(ExpiresIn(ttl)) = expires_in {
+						res.headers_mut().insert(
+							HeaderName::from_static("x-token-expires-in"),
+							HeaderValue::from(ttl.as_secs()),
+						);
+					}
+					Ok(res)
+				}
+				Err(e) => Err(auth.reject(e, &req)),
+			}
+		})
+	}
+}
+
+/// Return true if the path is the prefix or one of its sub-paths
+fn skipped(path: &str, prefix: &str) -> bool {
+	match path.strip_prefix(prefix) {
+		Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+		None => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn skip_sub_paths() {
+		assert!(skipped("/public", "/public"));
+		assert!(skipped("/public/logo.png", "/public"));
+		assert!(skipped("/static/app.js", "/static/"));
+		assert!(!skipped("/public-admin", "/public"));
+		assert!(!skipped("/api", "/public"));
 	}
 }
@@ -0,0 +1,110 @@
+use std::{
+	collections::HashMap,
+	net::IpAddr,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+// number of tracked peers above which stale entries are purged
+const PURGE_THRESHOLD: usize = 10_000;
+
+// maximum number of tracked peers
+const MAX_PEERS: usize = 100_000;
+
+// minimum delay between two purges, so that a full table doesn't cost a scan per failure
+const PURGE_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Entry {
+	// failures counted in the current window
+	failures: u32,
+	// start of the current window
+	since: Instant,
+	// end of the block if any
+	blocked_until: Option<Instant>,
+}
+
+/// Per peer ip limiter that blocks clients presenting too many invalid tokens. IPv6 peers are
+/// tracked by /64 network as a client usually owns the whole prefix
+pub struct FailureLimiter {
+	max_failures: u32,
+	window: Duration,
+	block: Duration,
+	// tracked peers and time of the last purge
+	entries: Mutex<(HashMap<IpAddr, Entry>, Instant)>,
+}
+
+impl FailureLimiter {
+	/// Block a peer for `block` after `max_failures` failures within `window`
+	pub fn new(max_failures: u32, window: Duration, block: Duration) -> Self {
+		Self {
+			max_failures,
+			window,
+			block,
+			entries: Mutex::new((HashMap::new(), Instant::now())),
+		}
+	}
+
+	/// Return the remaining block duration if the peer is blocked
+	pub fn blocked(&self, ip: IpAddr) -> Option<Duration> {
+		let now = Instant::now();
+		self.entries
+			.lock()
+			.unwrap()
+			.0
+			.get(&bucket(ip))
+			.and_then(|entry| entry.blocked_until)
+			.filter(|until| *until > now)
+			.map(|until| until - now)
+	}
+
+	/// Record a failed authentication for the peer. Stale peers are purged at most every 10
+	/// seconds, and the failures of new peers are not counted while the table is full
+	pub fn failure(&self, ip: IpAddr) {
+		let now = Instant::now();
+		let ip = bucket(ip);
+		let mut guard = self.entries.lock().unwrap();
+		let (entries, purged) = &mut *guard;
+		if entries.len() > PURGE_THRESHOLD && now - *purged >= PURGE_INTERVAL {
+			*purged = now;
+			let window = self.window;
+			entries.retain(|_, entry| {
+				now - entry.since < window || entry.blocked_until.map_or(false, |until| until > now)
+			});
+			if entries.len() >= MAX_PEERS {
+				// forget the failures of the peers that are not blocked
+				entries.retain(|_, entry| entry.blocked_until.map_or(false, |until| until > now));
+			}
+		}
+		if entries.len() >= MAX_PEERS && !entries.contains_key(&ip) {
+			return;
+		}
+		let entry = entries.entry(ip).or_insert(Entry {
+			failures: 0,
+			since: now,
+			blocked_until: None,
+		});
+		if now - entry.since >= self.window {
+			entry.failures = 0;
+			entry.since = now;
+		}
+		entry.failures += 1;
+		if entry.failures >= self.max_failures {
+			entry.blocked_until = Some(now + self.block);
+			entry.failures = 0;
+			entry.since = now;
+		}
+	}
+
+	/// Forget the failures of a peer that successfully authenticated
+	pub fn success(&self, ip: IpAddr) {
+		self.entries.lock().unwrap().0.remove(&bucket(ip));
+	}
+}
+
+/// Return the key of the peer: its address, or its /64 network for IPv6
+fn bucket(ip: IpAddr) -> IpAddr {
+	match ip.to_canonical() {
+		IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & !(u128::MAX >> 64)).into()),
+		ip => ip,
+	}
+}
@@ -0,0 +1,249 @@
+use actix_utils::future::{ready, Ready};
+use actix_web::{
+	dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+	error::{ErrorUnauthorized, InternalError},
+	http::header::AUTHORIZATION,
+	Error, HttpMessage, HttpResponse,
+};
+use macaroon::{ByteString, Caveat, Macaroon, MacaroonKey, Verifier};
+use std::{
+	rc::Rc,
+	sync::Arc,
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+	chain::{self, AuthContext, Authenticator},
+	jwtauth::ErrorHandler,
+};
+use crate::{result::Error as AuthError, tokens::TokenIdentity};
+use futures_util::future::LocalBoxFuture;
+
+/// Verification of macaroons minted with a root key, for services migrating from macaroon based
+/// delegation. The request carries the macaroon followed by its discharges (comma separated) in
+/// `Authorization: Macaroon <macaroon>,<discharge>...`. Besides the exact predicates registered,
+/// the first party caveats understood are
+/// - `time < <unix time>`: expiration of the macaroon
+/// - `path ^ <prefix>`: prefix of the request paths
+/// - `method = <method>[,<method>...]`: methods of the requests
+///
+/// The identifier of the macaroon is inserted in the request extensions as a `TokenIdentity`
+#[derive(Clone)]
+pub struct MacaroonAuth {
+	key: MacaroonKey,
+	// authentication scheme of the Authorization header
+	scheme: String,
+	// predicates satisfied by every request
+	exact: Vec<String>,
+	// custom rejection response
+	error_handler: Option<ErrorHandler>,
+}
+
+impl MacaroonAuth {
+	/// Verify the macaroons minted with the given root key
+	pub fn new(root_key: &[u8]) -> Self {
+		Self {
+			key: MacaroonKey::generate(root_key),
+			scheme: "Macaroon".to_owned(),
+			exact: Vec::new(),
+			error_handler: None,
+		}
+	}
+
+	/// Read the macaroons after the given scheme in the Authorization header (`Macaroon` by
+	/// default)
+	pub fn scheme(mut self, scheme: &str) -> Self {
+		self.scheme = scheme.to_owned();
+		self
+	}
+
+	/// Satisfy the caveats with the given predicate (eg. `account = 3735928559`)
+	pub fn satisfy(mut self, predicate: &str) -> Self {
+		self.exact.push(predicate.to_owned());
+		self
+	}
+
+	/// Build the response of rejected requests instead of the default 401 `not authorized`
+	pub fn error_handler<F>(mut self, handler: F) -> Self
+	where
+		F: Fn(&AuthError, &ServiceRequest) -> HttpResponse + Send + Sync + 'static,
+	{
+		self.error_handler = Some(Arc::new(handler));
+		self
+	}
+
+	/// Convert an authentication error to an actix error
+	pub(crate) fn reject(&self, e: AuthError, req: &ServiceRequest) -> Error {
+		match &self.error_handler {
+			Some(handler) => {
+				let res = handler(&e, req);
+				InternalError::from_response(e, res).into()
+			}
+			None => ErrorUnauthorized("not authorized"),
+		}
+	}
+
+	/// Return true if the request satisfies the given first party caveat
+	fn satisfies(&self, predicate: &str, req: &ServiceRequest) -> bool {
+		if self.exact.iter().any(|exact| exact == predicate) {
+			return true;
+		}
+		match predicate.split_once(' ') {
+			Some(("time", condition)) => condition
+				.strip_prefix("< ")
+				.and_then(|time| time.trim().parse::<u64>().ok())
+				.map_or(false, |time| {
+					let now = SystemTime::now()
+						.duration_since(UNIX_EPOCH)
+						.map(|d| d.as_secs())
+						.unwrap_or_default();
+					now < time
+				}),
+			Some(("path", condition)) => condition
+				.strip_prefix("^ ")
+				.map_or(false, |prefix| req.path().starts_with(prefix.trim())),
+			Some(("method", condition)) => condition.strip_prefix("= ").map_or(false, |methods| {
+				methods
+					.split(',')
+					.any(|method| method.trim().eq_ignore_ascii_case(req.method().as_str()))
+			}),
+			_ => false,
+		}
+	}
+
+	/// Check the macaroon of the request with its discharges and return its identifier
+	pub(crate) fn check(&self, req: &ServiceRequest) -> Result<TokenIdentity, AuthError> {
+		let value = req
+			.headers()
+			.get(AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| chain::credentials(value, &self.scheme))
+			.ok_or(AuthError::NoToken)?;
+		let mut macaroons = value
+			.split(',')
+			.map(|macaroon| Macaroon::deserialize(macaroon.trim()))
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|e| {
+				tracing::debug!("Failed to deserialize a macaroon: {:?}", e);
+				AuthError::InvalidToken
+			})?;
+		let macaroon = macaroons.remove(0);
+		// the caveats are evaluated beforehand as the verifier only takes function pointers
+		let mut verifier = Verifier::default();
+		for caveat in std::iter::once(&macaroon)
+			.chain(&macaroons)
+			.flat_map(Macaroon::first_party_caveats)
+		{
+			if let Caveat::FirstParty(caveat) = caveat {
+				let predicate = caveat.predicate();
+				if self.satisfies(&String::from_utf8_lossy(&predicate.0), req) {
+					verifier.satisfy_exact(predicate);
+				}
+			}
+		}
+		macaroon
+			.verify(&self.key, &mut verifier, macaroons)
+			.map_err(|e| {
+				tracing::debug!("Macaroon rejected: {:?}", e);
+				AuthError::InvalidToken
+			})?;
+		let identifier: ByteString = macaroon.identifier();
+		let identity = TokenIdentity::new(&String::from_utf8_lossy(&identifier.0));
+		tracing::debug!(client = identity.name.as_str(), "macaroon accepted");
+		Ok(identity)
+	}
+}
+
+impl Authenticator for MacaroonAuth {
+	fn authenticate<'a>(
+		&'a self,
+		req: &'a mut ServiceRequest,
+	) -> LocalBoxFuture<'a, Result<AuthContext, AuthError>> {
+		let res = self.check(req).map(AuthContext::Token);
+		Box::pin(async move { res })
+	}
+
+	fn reject(&self, e: AuthError, req: &ServiceRequest) -> Error {
+		MacaroonAuth::reject(self, e, req)
+	}
+}
+
+impl<S, B> Transform<S, ServiceRequest> for MacaroonAuth
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+	S::Future: 'static,
+	B: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Transform = MacaroonAuthMiddleware<S>;
+	type InitError = ();
+	type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		ready(Ok(MacaroonAuthMiddleware {
+			service: Rc::new(service),
+			auth: self.clone(),
+		}))
+	}
+}
+
+pub struct MacaroonAuthMiddleware<S> {
+	service: Rc<S>,
+	auth: MacaroonAuth,
+}
+
+impl<S, B> Service<ServiceRequest> for MacaroonAuthMiddleware<S>
+where
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+	S::Future: 'static,
+	B: 'static,
+{
+	type Response = ServiceResponse<B>;
+	type Error = Error;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+	forward_ready!(service);
+
+	fn call(&self, req: ServiceRequest) -> Self::Future {
+		let service = self.service.clone();
+		let auth = self.auth.clone();
+		Box::pin(async move {
+			match auth.check(&req) {
+				Ok(identity) => {
+					req.extensions_mut().insert(identity);
+					service.call(req).await
+				}
+				Err(e) => Err(auth.reject(e, &req)),
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+	use macaroon::Format;
+
+	#[test]
+	fn verify_caveats() {
+		let auth = MacaroonAuth::new(b"root key").satisfy("account = 42");
+		let mut macaroon =
+			Macaroon::create(None, &MacaroonKey::generate(b"root key"), "ci".into()).unwrap();
+		macaroon.add_first_party_caveat("account = 42".into());
+		macaroon.add_first_party_caveat("path ^ /api/".into());
+		macaroon.add_first_party_caveat("method = GET,HEAD".into());
+		macaroon.add_first_party_caveat("time < 99999999999".into());
+		let header = format!("Macaroon {}", macaroon.serialize(Format::V2).unwrap());
+		let req = TestRequest::with_uri("/api/builds")
+			.insert_header((AUTHORIZATION, header.as_str()))
+			.to_srv_request();
+		assert_eq!(auth.check(&req).unwrap().name, "ci");
+		let req = TestRequest::post()
+			.uri("/api/builds")
+			.insert_header((AUTHORIZATION, header.as_str()))
+			.to_srv_request();
+		assert!(matches!(auth.check(&req), Err(AuthError::InvalidToken)));
+	}
+}
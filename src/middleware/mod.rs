@@ -1,2 +1,14 @@
 pub mod tokenauth;
+pub mod basicauth;
+pub mod anyauth;
+pub mod chain;
 pub mod jwtauth;
+pub mod reject;
+pub mod limiter;
+pub mod network;
+pub mod audit;
+#[cfg(feature = "otel")]
+pub mod otel;
+#[cfg(feature = "macaroon")]
+pub mod macaroonauth;
+pub mod identity;
@@ -0,0 +1,191 @@
+use crate::result::{Error, Result};
+
+use actix_web::{
+	dev::ServiceRequest,
+	http::header::{HeaderName, FORWARDED},
+};
+use std::{net::IpAddr, str::FromStr};
+
+/// An ip network in CIDR notation (`10.0.0.0/8`, `fd00::/8`) or a single address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+	addr: IpAddr,
+	prefix: u8,
+}
+
+impl Cidr {
+	/// Return true if the address belongs to the network
+	pub fn contains(&self, ip: IpAddr) -> bool {
+		match (self.addr, ip.to_canonical()) {
+			(IpAddr::V4(net), IpAddr::V4(ip)) => {
+				let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+				u32::from(net) & mask == u32::from(ip) & mask
+			}
+			(IpAddr::V6(net), IpAddr::V6(ip)) => {
+				let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+				u128::from(net) & mask == u128::from(ip) & mask
+			}
+			_ => false,
+		}
+	}
+}
+
+impl FromStr for Cidr {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		let invalid = || Error::InvalidNetwork(s.to_owned());
+		let (addr, prefix) = match s.split_once('/') {
+			Some((addr, prefix)) => (addr, Some(prefix)),
+			None => (s, None),
+		};
+		let addr = addr
+			.parse::<IpAddr>()
+			.map_err(|_| invalid())?
+			.to_canonical();
+		let max = if addr.is_ipv4() { 32 } else { 128 };
+		let prefix = match prefix {
+			Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+			None => max,
+		};
+		if prefix > max {
+			return Err(invalid());
+		}
+		Ok(Self { addr, prefix })
+	}
+}
+
+/// Networks whose requests skip token validation, with the reverse proxies trusted to
+/// report the client address in `Forwarded` or `X-Forwarded-For`
+#[derive(Clone, Debug, Default)]
+pub struct NetworkBypass {
+	networks: Vec<Cidr>,
+	proxies: Vec<Cidr>,
+}
+
+impl NetworkBypass {
+	/// Empty allowlist
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Let requests coming from the network through
+	pub fn allow(mut self, network: Cidr) -> Self {
+		self.networks.push(network);
+		self
+	}
+
+	/// Trust the forwarding headers set by proxies of this network
+	pub fn trust_proxy(mut self, network: Cidr) -> Self {
+		self.proxies.push(network);
+		self
+	}
+
+	/// Return true if no network is allowed
+	pub fn is_empty(&self) -> bool {
+		self.networks.is_empty()
+	}
+
+	/// Return true if the client address belongs to an allowed network
+	pub fn bypass(&self, req: &ServiceRequest) -> bool {
+		!self.is_empty()
+			&& self
+				.client_ip(req)
+				.map_or(false, |ip| self.networks.iter().any(|net| net.contains(ip)))
+	}
+
+	/// Return the client address: the peer address, or the closest address not belonging to a
+	/// trusted proxy in the forwarding headers when the peer is a trusted proxy
+	pub fn client_ip(&self, req: &ServiceRequest) -> Option<IpAddr> {
+		let trusted = |ip: &IpAddr| self.proxies.iter().any(|net| net.contains(*ip));
+		let peer = req.peer_addr()?.ip().to_canonical();
+		if !trusted(&peer) {
+			return Some(peer);
+		}
+		// hops from the client to the last proxy (None for unparsable or obfuscated entries)
+		let hops = forwarded_for(req);
+		for hop in hops.iter().rev() {
+			match hop {
+				Some(ip) if trusted(ip) => continue,
+				hop => return *hop,
+			}
+		}
+		hops.first().copied().flatten().or(Some(peer))
+	}
+}
+
+/// Parse an address of a forwarding header (`192.0.2.1`, `"[2001:db8::1]:4711"`, `192.0.2.1:80`)
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+	let hop = hop.trim().trim_matches('"');
+	if let Some(rest) = hop.strip_prefix('[') {
+		return rest.split(']').next()?.parse().ok();
+	}
+	hop.parse()
+		.ok()
+		.or_else(|| hop.rsplit_once(':')?.0.parse().ok())
+		.map(|ip: IpAddr| ip.to_canonical())
+}
+
+/// Addresses reported by `Forwarded` or by `X-Forwarded-For` if the former is absent
+fn forwarded_for(req: &ServiceRequest) -> Vec<Option<IpAddr>> {
+	let headers = req.headers();
+	if headers.contains_key(FORWARDED) {
+		headers
+			.get_all(FORWARDED)
+			.filter_map(|value| value.to_str().ok())
+			.flat_map(|value| value.split(','))
+			.map(|element| {
+				element
+					.split(';')
+					.filter_map(|pair| pair.split_once('='))
+					.find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+					.and_then(|(_, value)| parse_hop(value))
+			})
+			.collect()
+	} else {
+		headers
+			.get_all(HeaderName::from_static("x-forwarded-for"))
+			.filter_map(|value| value.to_str().ok())
+			.flat_map(|value| value.split(','))
+			.map(parse_hop)
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use actix_web::test::TestRequest;
+
+	#[test]
+	fn cidr_contains() {
+		let net = "10.0.0.0/8".parse::<Cidr>().unwrap();
+		assert!(net.contains("10.1.2.3".parse().unwrap()));
+		assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+		assert!(!net.contains("11.0.0.1".parse().unwrap()));
+		assert!("0.0.0.0/0"
+			.parse::<Cidr>()
+			.unwrap()
+			.contains("1.2.3.4".parse().unwrap()));
+		assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+	}
+
+	#[test]
+	fn client_ip_behind_proxy() {
+		let bypass = NetworkBypass::new()
+			.allow("10.42.0.0/16".parse().unwrap())
+			.trust_proxy("192.168.1.1".parse().unwrap());
+		let req = TestRequest::default()
+			.peer_addr("192.168.1.1:1234".parse().unwrap())
+			.insert_header(("x-forwarded-for", "1.2.3.4, 10.42.0.7"))
+			.to_srv_request();
+		assert_eq!(bypass.client_ip(&req), "10.42.0.7".parse().ok());
+		assert!(bypass.bypass(&req));
+		// forwarding headers of untrusted peers are ignored
+		let req = TestRequest::default()
+			.peer_addr("1.2.3.4:1234".parse().unwrap())
+			.insert_header(("x-forwarded-for", "10.42.0.7"))
+			.to_srv_request();
+		assert!(!bypass.bypass(&req));
+	}
+}
@@ -0,0 +1,25 @@
+use crate::result::Error as AuthError;
+
+use jsonwebtoken as jwt;
+use serde_json::Value;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Record the identity of an accepted request on the current span
+pub fn accepted(tokendata: &jwt::TokenData<Value>) {
+	let span = Span::current();
+	span.set_attribute("auth.outcome", "accepted");
+	if let Some(sub) = tokendata.claims.get("sub").and_then(Value::as_str) {
+		span.set_attribute("enduser.id", sub.to_owned());
+	}
+	if let Some(kid) = &tokendata.header.kid {
+		span.set_attribute("jwt.kid", kid.to_owned());
+	}
+}
+
+/// Record the failure reason of a rejected request on the current span
+pub fn rejected(e: &AuthError) {
+	let span = Span::current();
+	span.set_attribute("auth.outcome", "rejected");
+	span.set_attribute("auth.failure_reason", e.reason());
+}
@@ -0,0 +1,355 @@
+use crate::{data, result::Error as AuthError};
+
+use actix_web::{
+	http::{
+		header::{Accept, Header, HeaderName, LOCATION, RETRY_AFTER, WWW_AUTHENTICATE},
+		StatusCode,
+	},
+	HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use jsonwebtoken::errors::ErrorKind;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Format of the body sent back when a request is rejected
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+	/// Plain text message
+	#[default]
+	Text,
+	/// RFC 7807 `application/problem+json` document
+	Problem,
+}
+
+/// Redirection of browsers to a login page instead of a 401 response
+#[derive(Clone, Debug)]
+pub struct LoginRedirect {
+	url: String,
+	param: String,
+}
+
+impl LoginRedirect {
+	/// Redirect to the given login url, passing the requested url in the `return_to` parameter
+	pub fn new(url: &str) -> Self {
+		Self {
+			url: url.to_owned(),
+			param: "return_to".to_owned(),
+		}
+	}
+
+	/// Set the name of the parameter carrying the requested url
+	pub fn param(mut self, param: &str) -> Self {
+		self.param = param.to_owned();
+		self
+	}
+
+	/// Return true if the client prefers html (ie. is a browser)
+	pub fn applies(&self, req: &HttpRequest) -> bool {
+		Accept::parse(req)
+			.map(|accept| accept.preference().essence_str() == "text/html")
+			.unwrap_or(false)
+	}
+
+	/// Build the redirect response
+	pub fn response(&self, req: &HttpRequest) -> HttpResponse {
+		let query = serde_urlencoded::to_string([(self.param.as_str(), req.uri().to_string())])
+			.unwrap_or_default();
+		let sep = if self.url.contains('?') { '&' } else { '?' };
+		HttpResponse::Found()
+			.insert_header((LOCATION, format!("{}{}{}", self.url, sep, query)))
+			.finish()
+	}
+}
+
+/// Template of the rejection body matching the conventions of an organization, where `{status}`,
+/// `{reason}`, `{message}`, `{realm}` and `{request_id}` are replaced by the status code, the
+/// short identifier of the error, its message, the realm and the id of the request. The values
+/// are escaped when the content type is json
+///
+/// ```ignore
+/// ErrorTemplate::json(r#"{"code": "{reason}", "message": "{message}", "trace": "{request_id}"}"#)
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ErrorTemplate {
+	body: String,
+	content_type: String,
+	request_id_header: String,
+}
+
+impl Default for ErrorTemplate {
+	fn default() -> Self {
+		Self {
+			body: "{message}".to_owned(),
+			content_type: "text/plain; charset=utf-8".to_owned(),
+			request_id_header: "x-request-id".to_owned(),
+		}
+	}
+}
+
+impl ErrorTemplate {
+	/// Render plain text bodies from the given template
+	pub fn new(body: &str) -> Self {
+		Self {
+			body: body.to_owned(),
+			..Self::default()
+		}
+	}
+
+	/// Render json bodies from the given template
+	pub fn json(body: &str) -> Self {
+		Self::new(body).content_type("application/json")
+	}
+
+	/// Set the content type of the bodies
+	pub fn content_type(mut self, content_type: &str) -> Self {
+		self.content_type = content_type.to_owned();
+		self
+	}
+
+	/// Read the request id from the given header (`X-Request-Id` by default)
+	pub fn request_id_header(mut self, name: &str) -> Self {
+		self.request_id_header = name.to_owned();
+		self
+	}
+
+	/// Build the response for a rejected request
+	pub fn response(&self, e: &AuthError, realm: Option<&str>, req: &HttpRequest) -> HttpResponse {
+		let status = status(e);
+		let request_id = req
+			.headers()
+			.get(self.request_id_header.as_str())
+			.and_then(|value| value.to_str().ok())
+			.unwrap_or_default();
+		let json = self.content_type.contains("json");
+		let escape = |value: &str| {
+			if json {
+				let quoted = Value::from(value).to_string();
+				quoted[1..quoted.len() - 1].to_owned()
+			} else {
+				value.to_owned()
+			}
+		};
+		let body = [
+			("{status}", status.as_u16().to_string()),
+			("{reason}", e.reason().to_owned()),
+			("{message}", e.to_string()),
+			("{realm}", realm.unwrap_or_default().to_owned()),
+			("{request_id}", request_id.to_owned()),
+		]
+		.iter()
+		.fold(self.body.clone(), |body, (placeholder, value)| {
+			body.replace(placeholder, &escape(value))
+		});
+		builder(e, realm)
+			.content_type(self.content_type.as_str())
+			.body(body)
+	}
+}
+
+/// RFC 6750 error code of a rejected request (None when no token was presented)
+pub fn bearer_error(e: &AuthError) -> Option<&'static str> {
+	match e {
+		AuthError::NoBearer
+		| AuthError::NoToken
+		| AuthError::NoCredentials
+		| AuthError::TooManyFailures(_)
+		| AuthError::KeysUnavailable
+		| AuthError::Userinfo(_) => None,
+		AuthError::Claim(..) | AuthError::ClaimNotFound(_) | AuthError::Forbidden(_) => {
+			Some("insufficient_scope")
+		}
+		// RFC 9470
+		AuthError::StepUp(_) => Some("insufficient_user_authentication"),
+		_ => Some("invalid_token"),
+	}
+}
+
+/// Status code of a rejected request: 403 for insufficient scope, 429 for blocked peers, 503
+/// when the keys or the userinfo are not available, 401 otherwise
+pub fn status(e: &AuthError) -> StatusCode {
+	match e {
+		AuthError::TooManyFailures(_) => return StatusCode::TOO_MANY_REQUESTS,
+		AuthError::KeysUnavailable | AuthError::Userinfo(_) => {
+			return StatusCode::SERVICE_UNAVAILABLE
+		}
+		_ => (),
+	}
+	match bearer_error(e) {
+		Some("insufficient_scope") => StatusCode::FORBIDDEN,
+		_ => StatusCode::UNAUTHORIZED,
+	}
+}
+
+/// Build the RFC 6750 `WWW-Authenticate` challenge for a rejected request. The description is
+/// the reason code only, as the error message can hold the values of the claims
+pub fn challenge(e: &AuthError, realm: Option<&str>) -> String {
+	let mut params = Vec::new();
+	if let Some(realm) = realm {
+		params.push(format!("realm=\"{}\"", quoted(realm)));
+	}
+	if let Some(code) = bearer_error(e) {
+		params.push(format!("error=\"{}\"", code));
+		params.push(format!("error_description=\"{}\"", e.reason()));
+	}
+	if params.is_empty() {
+		"Bearer".to_owned()
+	} else {
+		format!("Bearer {}", params.join(", "))
+	}
+}
+
+/// Keep only the characters allowed inside a challenge quoted string
+pub(crate) fn quoted(s: &str) -> String {
+	s.chars()
+		.filter_map(|c| match c {
+			'"' => Some('\''),
+			'\\' => None,
+			' '..='~' => Some(c),
+			_ => None,
+		})
+		.collect()
+}
+
+/// Precise description of a failure for the debug mode: the error, its reason and the time
+/// claims of the token with the current time of the validating clock (unix time) when they
+/// caused the failure
+pub fn diagnostic(e: &AuthError, token: Option<&str>, now: u64) -> String {
+	let mut diagnostic = format!("{} ({})", e, e.reason());
+	let time = match e {
+		AuthError::JwtError(error) => matches!(
+			error.kind(),
+			ErrorKind::ExpiredSignature | ErrorKind::ImmatureSignature
+		),
+		AuthError::IssuedInFuture(_) => true,
+		_ => false,
+	};
+	if let Some(claims) = token.filter(|_| time).and_then(data::unverified_claims) {
+		for name in ["exp", "nbf", "iat"] {
+			if let Some(value) = claims.get(name) {
+				diagnostic.push_str(&format!(", {} {}", name, value));
+			}
+		}
+		diagnostic.push_str(&format!(", now {}", now));
+	}
+	diagnostic
+}
+
+/// Build the response for a rejected request in the given format
+pub fn response(e: &AuthError, format: ErrorFormat, realm: Option<&str>) -> HttpResponse {
+	build(e, format, realm, None)
+}
+
+/// Same as response with the given diagnostic in the body and in a `X-Auth-Debug` header, for
+/// development only
+pub fn debug_response(
+	e: &AuthError,
+	format: ErrorFormat,
+	realm: Option<&str>,
+	diagnostic: &str,
+) -> HttpResponse {
+	build(e, format, realm, Some(diagnostic))
+}
+
+/// Start the response for a rejected request with its status and headers
+fn builder(e: &AuthError, realm: Option<&str>) -> HttpResponseBuilder {
+	let mut builder = HttpResponse::build(status(e));
+	builder.insert_header((WWW_AUTHENTICATE, challenge(e, realm)));
+	if let AuthError::TooManyFailures(secs) = e {
+		builder.insert_header((RETRY_AFTER, secs.to_string()));
+	}
+	builder
+}
+
+/// Build the response for a rejected request with an optional diagnostic
+fn build(
+	e: &AuthError,
+	format: ErrorFormat,
+	realm: Option<&str>,
+	diagnostic: Option<&str>,
+) -> HttpResponse {
+	let status = status(e);
+	let mut builder = builder(e, realm);
+	if let Some(diagnostic) = diagnostic {
+		builder.insert_header((HeaderName::from_static("x-auth-debug"), quoted(diagnostic)));
+	}
+	// the message is only disclosed in debug mode
+	let detail = diagnostic.unwrap_or_else(|| e.reason());
+	match format {
+		ErrorFormat::Text => builder
+			.content_type("text/plain; charset=utf-8")
+			.body(match realm {
+				Some(realm) => format!("Not authorized to {} - {}", realm, detail),
+				None => format!("Not authorized - {}", detail),
+			}),
+		ErrorFormat::Problem => {
+			let mut problem = json!({
+				"type": "about:blank",
+				"title": status.canonical_reason(),
+				"status": status.as_u16(),
+				"detail": detail,
+			});
+			if let Some(realm) = realm {
+				problem["realm"] = realm.into();
+			}
+			if diagnostic.is_some() {
+				problem["reason"] = Value::from(e.reason());
+			}
+			builder
+				.content_type("application/problem+json")
+				.body(problem.to_string())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn challenge_without_token() {
+		assert_eq!(challenge(&AuthError::NoBearer, None), "Bearer");
+		assert_eq!(
+			challenge(&AuthError::NoBearer, Some("staticserve")),
+			"Bearer realm=\"staticserve\""
+		);
+	}
+
+	#[actix_rt::test]
+	async fn render_template() {
+		use actix_web::{body::to_bytes, test::TestRequest};
+
+		let template = ErrorTemplate::json(r#"{"code":"{reason}","id":"{request_id}"}"#);
+		let req = TestRequest::default()
+			.insert_header(("x-request-id", "42\""))
+			.to_http_request();
+		let res = template.response(&AuthError::NoBearer, None, &req);
+		assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+		let body = to_bytes(res.into_body()).await.unwrap();
+		assert_eq!(body, r#"{"code":"no_token","id":"42\""}"#);
+	}
+
+	#[test]
+	fn expired_diagnostic() {
+		let payload = base64::encode_config(r#"{"exp":1}"#, base64::URL_SAFE_NO_PAD);
+		let token = format!("e30.{}.sig", payload);
+		let e = AuthError::JwtError(ErrorKind::ExpiredSignature.into());
+		let diagnostic = diagnostic(&e, Some(&token), 2);
+		assert!(diagnostic.ends_with(" (invalid_token), exp 1, now 2"));
+	}
+
+	#[test]
+	fn challenge_insufficient_scope() {
+		let e = AuthError::Claim(
+			"iss".to_owned(),
+			"\"example.com\"".to_owned(),
+			"\"unknown\"".to_owned(),
+		);
+		assert_eq!(status(&e), StatusCode::FORBIDDEN);
+		assert_eq!(
+			challenge(&e, None),
+			"Bearer error=\"insufficient_scope\", error_description=\"claim_mismatch\""
+		);
+	}
+}
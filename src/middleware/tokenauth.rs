@@ -1,32 +1,310 @@
 use actix_utils::future::{ready, Ready};
 use actix_web::{
-	dev::{ServiceRequest, ServiceResponse, Service, Transform, forward_ready},
-	error::ErrorUnauthorized,
-	Error,
+	dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+	error::{ErrorUnauthorized, InternalError},
+	http::{
+		header::{HeaderName, AUTHORIZATION},
+		StatusCode,
+	},
+	Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
 };
-use actix_utils::future::{err, Either};
-use std::{
-	rc::Rc
+use std::{borrow::Cow, path::Path, rc::Rc, str::FromStr, sync::Arc, time::Duration};
+
+use super::{
+	chain::{self, AuthContext, Authenticator},
+	jwtauth::ErrorHandler,
+};
+use crate::{
+	config,
+	result::{Error as AuthError, Result as AuthResult},
+	tokens::{ApiKeyStore, Secret, TokenFile, TokenIdentity, TokenSet},
 };
+use futures_util::future::LocalBoxFuture;
 
 // There are two steps in middleware processing.
 // 1. Middleware initialization, middleware factory gets called with
 //    next service in chain as parameter.
 // 2. Middleware's call method gets called with normal request.
 
-#[derive(Clone, Default)]
-pub struct TokenAuth(Rc<String>);
+#[derive(Clone)]
+pub struct TokenAuth {
+	tokens: TokenSet,
+	// file the tokens are reloaded from
+	file: Option<Arc<TokenFile>>,
+	// header carrying the token
+	header: HeaderName,
+	// authentication scheme preceding the token in the header if any
+	scheme: Option<String>,
+	// query parameter carrying the token if allowed
+	query: Option<String>,
+	// cookie carrying the token if allowed
+	cookie: Option<String>,
+	// external store replacing the token set
+	store: Option<Arc<dyn ApiKeyStore + Send + Sync>>,
+	// custom rejection response
+	error_handler: Option<ErrorHandler>,
+}
 
-/*impl Default for TokenAuth {
+impl Default for TokenAuth {
 	fn default() -> Self {
-		Self(Rc::new(String::default()))
+		Self {
+			tokens: TokenSet::default(),
+			file: None,
+			header: HeaderName::from_static("token"),
+			scheme: None,
+			query: None,
+			cookie: None,
+			store: None,
+			error_handler: None,
+		}
 	}
-}*/
+}
 
 impl TokenAuth {
 	/// Construct `TokenAuth` middleware.
 	pub fn new(token: &str) -> Self {
-		Self(Rc::new(token.to_owned()))
+		Self::with_tokens([token])
+	}
+
+	/// Construct `TokenAuth` middleware accepting any of the given tokens (eg. one per client)
+	pub fn with_tokens<I, T>(tokens: I) -> Self
+	where
+		I: IntoIterator<Item = T>,
+		T: AsRef<str>,
+	{
+		Self::with_secrets(
+			tokens
+				.into_iter()
+				.map(|token| Secret::Plain(token.as_ref().to_owned())),
+		)
+	}
+
+	/// Construct `TokenAuth` middleware accepting any of the given secrets, which can be hashes
+	/// so that the plain text tokens never live in the server configuration
+	pub fn with_secrets<I>(secrets: I) -> Self
+	where
+		I: IntoIterator<Item = Secret>,
+	{
+		Self {
+			tokens: TokenSet::new(secrets),
+			..Self::default()
+		}
+	}
+
+	/// Construct `TokenAuth` middleware accepting any of the given secrets, each one identifying
+	/// a client
+	pub fn with_identities<I>(tokens: I) -> Self
+	where
+		I: IntoIterator<Item = (Secret, TokenIdentity)>,
+	{
+		Self {
+			tokens: TokenSet::with_identities(tokens),
+			..Self::default()
+		}
+	}
+
+	/// Construct `TokenAuth` middleware sharing an existing set of secrets
+	pub fn from_set(tokens: TokenSet) -> Self {
+		Self {
+			tokens,
+			..Self::default()
+		}
+	}
+
+	/// Construct `TokenAuth` middleware looking the tokens up in an external store (database,
+	/// cache...) on each request. Wrap the store in a `CachedStore` to limit the lookups
+	pub fn with_store<K>(store: K) -> Self
+	where
+		K: ApiKeyStore + Send + Sync + 'static,
+	{
+		Self {
+			store: Some(Arc::new(store)),
+			..Self::default()
+		}
+	}
+
+	/// Return a handle on the accepted secrets (shared by all the clones of the middleware) to
+	/// add or revoke tokens at runtime. When the token file changes, its secrets are replaced
+	/// but the tokens added and revoked at runtime are kept
+	pub fn tokens(&self) -> TokenSet {
+		self.tokens.clone()
+	}
+
+	/// Construct `TokenAuth` middleware accepting the current secret, and the previous one during
+	/// the grace period, for a zero-downtime rotation of the shared secret
+	pub fn rotating(current: Secret, previous: Secret, grace: Duration) -> Self {
+		let auth = Self::with_secrets([previous]);
+		auth.tokens.rotate("", current, grace);
+		auth
+	}
+
+	/// Construct `TokenAuth` middleware accepting the secrets of a file (one per line, hashes
+	/// allowed, optionally after the client name and a space), which is re-read when it changes
+	/// so that rotating a mounted Kubernetes Secret doesn't require a restart
+	pub fn from_file<P: AsRef<Path>>(path: P) -> AuthResult<Self> {
+		let tokens = TokenSet::default();
+		let file = TokenFile::load(path.as_ref(), &tokens)?;
+		Ok(Self {
+			tokens,
+			file: Some(Arc::new(file)),
+			..Self::default()
+		})
+	}
+
+	/// Construct `TokenAuth` middleware from the environment: secrets are read from the file
+	/// named by `AUTH_TOKEN_FILE` or from `AUTH_TOKEN` (comma separated, hashes allowed), in the
+	/// header named by `AUTH_HEADER` (`token` by default) after the `AUTH_SCHEME` if any
+	pub fn from_env() -> AuthResult<Self> {
+		let auth = match config::var("AUTH_TOKEN_FILE")? {
+			Some(path) => Self::from_file(path)?,
+			None => Self::with_secrets(
+				config::required_var("AUTH_TOKEN")?
+					.split(',')
+					.map(str::trim)
+					.filter(|secret| !secret.is_empty())
+					.map(Secret::from_str)
+					.collect::<AuthResult<Vec<_>>>()?,
+			),
+		};
+		match config::var("AUTH_HEADER")? {
+			Some(name) => {
+				let header = HeaderName::from_str(&name)
+					.map_err(|_| AuthError::Config(format!("invalid AUTH_HEADER {}", name)))?;
+				let scheme = config::var("AUTH_SCHEME")?;
+				Ok(auth.header(header, scheme.as_deref()))
+			}
+			None => Ok(auth),
+		}
+	}
+
+	/// Read the token from the given header (`token` by default), after the given
+	/// authentication scheme if any (eg. `Bearer`)
+	pub fn header(mut self, name: HeaderName, scheme: Option<&str>) -> Self {
+		self.header = name;
+		self.scheme = scheme.map(|scheme| scheme.to_owned());
+		self
+	}
+
+	/// Read the token from `Authorization: Bearer <token>`
+	pub fn bearer(self) -> Self {
+		self.header(AUTHORIZATION, Some("Bearer"))
+	}
+
+	/// Also accept the token in the given query parameter (for webhooks or download urls where
+	/// headers can't be set). Beware that urls usually end up in access logs
+	pub fn query(mut self, name: &str) -> Self {
+		self.query = Some(name.to_owned());
+		self
+	}
+
+	/// Also accept the token in the given cookie
+	pub fn cookie(mut self, name: &str) -> Self {
+		self.cookie = Some(name.to_owned());
+		self
+	}
+
+	/// Build the response of rejected requests instead of the default 401 `not authorized`
+	pub fn error_handler<F>(mut self, handler: F) -> Self
+	where
+		F: Fn(&AuthError, &ServiceRequest) -> HttpResponse + Send + Sync + 'static,
+	{
+		self.error_handler = Some(Arc::new(handler));
+		self
+	}
+
+	/// Reject requests with the given status and an empty body (eg. 404 to hide the existence of
+	/// the endpoint)
+	pub fn error_status(self, status: StatusCode) -> Self {
+		self.error_handler(move |_, _| HttpResponse::build(status).finish())
+	}
+
+	/// Convert an authentication error to an actix error
+	pub(crate) fn reject(&self, e: AuthError, req: &ServiceRequest) -> Error {
+		match &self.error_handler {
+			Some(handler) => {
+				let res = handler(&e, req);
+				InternalError::from_response(e, res).into()
+			}
+			None => ErrorUnauthorized("not authorized"),
+		}
+	}
+
+	/// Extract the token from the header, then from the query string and cookie if allowed
+	fn token<'a>(&self, req: &'a ServiceRequest) -> Option<Cow<'a, str>> {
+		self.header_token(req)
+			.map(Cow::Borrowed)
+			.or_else(|| {
+				let name = self.query.as_ref()?;
+				serde_urlencoded::from_str::<Vec<(String, String)>>(req.query_string())
+					.ok()?
+					.into_iter()
+					.find(|(key, _)| key == name)
+					.map(|(_, token)| Cow::Owned(token))
+			})
+			.or_else(|| {
+				let name = self.cookie.as_ref()?;
+				req.cookie(name)
+					.map(|cookie| Cow::Owned(cookie.value().to_owned()))
+			})
+	}
+
+	/// Extract the token from the configured header
+	fn header_token<'a>(&self, req: &'a ServiceRequest) -> Option<&'a str> {
+		let value = req.headers().get(&self.header)?.to_str().ok()?;
+		match &self.scheme {
+			Some(scheme) => chain::credentials(value, scheme),
+			None => Some(value),
+		}
+	}
+
+	/// Check that the request carries one of the expected tokens and return the identity of its
+	/// client
+	pub(crate) async fn check(&self, req: &ServiceRequest) -> Result<TokenIdentity, AuthError> {
+		let token = self.token(req).ok_or(AuthError::NoToken)?;
+		let identity = match &self.store {
+			Some(store) => store.lookup(&token).await?,
+			None => {
+				if let Some(file) = &self.file {
+					file.reload(&self.tokens);
+				}
+				self.tokens.lookup(&token).await?
+			}
+		}
+		.ok_or(AuthError::InvalidToken)?;
+		tracing::debug!(client = identity.name.as_str(), "static token accepted");
+		Ok(identity)
+	}
+}
+
+impl Authenticator for TokenAuth {
+	fn authenticate<'a>(
+		&'a self,
+		req: &'a mut ServiceRequest,
+	) -> LocalBoxFuture<'a, Result<AuthContext, AuthError>> {
+		Box::pin(async move { self.check(req).await.map(AuthContext::Token) })
+	}
+
+	fn reject(&self, e: AuthError, req: &ServiceRequest) -> Error {
+		TokenAuth::reject(self, e, req)
+	}
+}
+
+/// Extract the identity of the client authenticated by `TokenAuth` or `BasicAuth`, directly or
+/// in an `AuthChain`. Requests without one are rejected with 401
+impl FromRequest for TokenIdentity {
+	type Error = Error;
+	type Future = Ready<Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+		let extensions = req.extensions();
+		let identity = extensions.get::<TokenIdentity>().cloned().or_else(|| {
+			match extensions.get::<AuthContext>() {
+				Some(AuthContext::Token(identity)) => Some(identity.clone()),
+				Some(AuthContext::Basic(user)) => Some(TokenIdentity::new(user)),
+				_ => None,
+			}
+		});
+		ready(identity.ok_or_else(|| ErrorUnauthorized("not authorized")))
 	}
 }
 
@@ -35,7 +313,7 @@ impl TokenAuth {
 // `B` - type of response's body
 impl<S, B> Transform<S, ServiceRequest> for TokenAuth
 where
-	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
 	S::Future: 'static,
 	B: 'static,
 {
@@ -47,38 +325,40 @@ where
 
 	fn new_transform(&self, service: S) -> Self::Future {
 		ready(Ok(TokenAuthMiddleware {
-			service,
-			token: self.0.clone(),
+			service: Rc::new(service),
+			auth: self.clone(),
 		}))
 	}
 }
 
 pub struct TokenAuthMiddleware<S> {
-	service: S,
-	token: Rc<String>,
+	service: Rc<S>,
+	auth: TokenAuth,
 }
 
 impl<S, B> Service<ServiceRequest> for TokenAuthMiddleware<S>
 where
-	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+	S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
 	S::Future: 'static,
+	B: 'static,
 {
 	type Response = ServiceResponse<B>;
 	type Error = Error;
-	type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+	type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
 	forward_ready!(service);
 
 	fn call(&self, req: ServiceRequest) -> Self::Future {
-		if let Some(token) = req
-			.headers()
-			.get("token")
-			.and_then(|token| token.to_str().ok())
-		{
-			if token == *self.token {
-				return Either::left(self.service.call(req));
+		let service = self.service.clone();
+		let auth = self.auth.clone();
+		Box::pin(async move {
+			match auth.check(&req).await {
+				Ok(identity) => {
+					req.extensions_mut().insert(identity);
+					service.call(req).await
+				}
+				Err(e) => Err(auth.reject(e, &req)),
 			}
-		}
-		Either::right(err(ErrorUnauthorized("not authorized")))
+		})
 	}
 }
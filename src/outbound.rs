@@ -0,0 +1,139 @@
+use crate::{
+	exchange::TokenExchange,
+	result::{Error, Result},
+	signer::Signer,
+};
+
+use actix_web::{http::Method, HttpRequest};
+use awc::{Client, ClientRequest};
+use serde_json::Value;
+use std::{
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+// minted tokens are renewed this long before their expiration
+const RENEW_MARGIN: Duration = Duration::from_secs(30);
+
+/// Where the bearer token of the outgoing requests comes from
+#[derive(Clone)]
+enum Source {
+	// same token for all the requests
+	Static(String),
+	// token minted with the given claims and reused until it nearly expires
+	Signer(Signer, Value),
+	// token validated by JwtAuth for the incoming request, exchanged for a downstream one
+	Exchange(TokenExchange),
+}
+
+/// Wrapper around `awc::Client` attaching a bearer token to the outgoing requests, the
+/// counterpart of the inbound middlewares for calls to other services
+pub struct TokenClient {
+	client: Client,
+	source: Source,
+	// last minted token with its renewal time
+	minted: Mutex<Option<(String, Instant)>>,
+}
+
+impl TokenClient {
+	/// Attach the given token
+	pub fn with_token(token: &str) -> Self {
+		Self::new(Source::Static(token.to_owned()))
+	}
+
+	/// Attach a token minted by the signer with the given claims (an object), renewed shortly
+	/// before it expires
+	pub fn with_signer(signer: Signer, claims: Value) -> Self {
+		Self::new(Source::Signer(signer, claims))
+	}
+
+	/// Attach the token of the incoming request exchanged for a downstream token (see
+	/// request_for)
+	pub fn with_exchange(exchange: TokenExchange) -> Self {
+		Self::new(Source::Exchange(exchange))
+	}
+
+	/// Construct a client attaching the tokens of the given source
+	fn new(source: Source) -> Self {
+		Self {
+			client: Client::default(),
+			source,
+			minted: Mutex::new(None),
+		}
+	}
+
+	/// Send the requests with the given client (timeouts, connector...)
+	pub fn client(mut self, client: Client) -> Self {
+		self.client = client;
+		self
+	}
+
+	/// Return the minted token if it is still valid long enough, or mint a new one
+	fn mint(&self, signer: &Signer, claims: &Value) -> Result<String> {
+		let mut minted = self.minted.lock().unwrap();
+		if let Some((token, renew)) = &*minted {
+			if Instant::now() < *renew {
+				return Ok(token.clone());
+			}
+		}
+		let token = signer.sign(claims)?;
+		let renew = Instant::now() + signer.lifetime().saturating_sub(RENEW_MARGIN);
+		*minted = Some((token.clone(), renew));
+		Ok(token)
+	}
+
+	/// Token to attach to a request made while handling the given incoming request if any
+	async fn token(&self, incoming: Option<&HttpRequest>) -> Result<String> {
+		match &self.source {
+			Source::Static(token) => Ok(token.clone()),
+			Source::Signer(signer, claims) => self.mint(signer, claims),
+			Source::Exchange(exchange) => {
+				let incoming = incoming.ok_or(Error::NoBearer)?;
+				Ok(exchange.exchange_request(incoming).await?.access_token)
+			}
+		}
+	}
+
+	/// Build a request carrying the token (fails with an exchange source, which needs the
+	/// incoming request)
+	pub async fn request(&self, method: Method, url: &str) -> Result<ClientRequest> {
+		let token = self.token(None).await?;
+		Ok(self.client.request(method, url).bearer_auth(token))
+	}
+
+	/// Build a request carrying the token, on behalf of the subject of the incoming request
+	pub async fn request_for(
+		&self,
+		incoming: &HttpRequest,
+		method: Method,
+		url: &str,
+	) -> Result<ClientRequest> {
+		let token = self.token(Some(incoming)).await?;
+		Ok(self.client.request(method, url).bearer_auth(token))
+	}
+
+	/// Build a GET request carrying the token
+	pub async fn get(&self, url: &str) -> Result<ClientRequest> {
+		self.request(Method::GET, url).await
+	}
+
+	/// Build a POST request carrying the token
+	pub async fn post(&self, url: &str) -> Result<ClientRequest> {
+		self.request(Method::POST, url).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[actix_rt::test]
+	async fn reuse_minted_token() {
+		let signer = Signer::from_jwk(include_str!("../tests/fixtures/signing.json")).unwrap();
+		let client = TokenClient::with_signer(signer, serde_json::json!({ "sub": "worker" }));
+		let token = client.token(None).await.unwrap();
+		assert_eq!(client.token(None).await.unwrap(), token);
+		let client = TokenClient::with_exchange(TokenExchange::new("https://idp/token", "api"));
+		assert!(matches!(client.token(None).await, Err(Error::NoBearer)));
+	}
+}
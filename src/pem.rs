@@ -0,0 +1,130 @@
+use jsonwebkey as jwk;
+
+// object identifiers of the public keys
+const RSA_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const EC_OID: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const P256_OID: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const P384_OID: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// Convert a PEM public key (SubjectPublicKeyInfo or PKCS#1 RSA) to a JWK
+#[cfg(any(feature = "k8s", feature = "vault"))]
+pub(crate) fn pem_key(pem: &str, kid: Option<&str>) -> Option<jwk::JsonWebKey> {
+	let label = pem.lines().next()?.trim();
+	let body = pem
+		.lines()
+		.filter(|line| !line.starts_with("-----"))
+		.collect::<String>();
+	let der = base64::decode(body.trim()).ok()?;
+	let mut key = match label {
+		"-----BEGIN PUBLIC KEY-----" => spki(&der)?,
+		"-----BEGIN RSA PUBLIC KEY-----" => rsa(&der)?,
+		_ => return None,
+	};
+	if let Some(kid) = kid {
+		key["kid"] = kid.into();
+	}
+	serde_json::from_value(key).ok()
+}
+
+/// Decode the certificates of a PEM bundle
+pub(crate) fn certificates(pem: &str) -> Vec<Vec<u8>> {
+	pem.split("-----BEGIN CERTIFICATE-----")
+		.skip(1)
+		.filter_map(|block| {
+			let body = block.split("-----END CERTIFICATE-----").next()?;
+			base64::decode(body.lines().map(str::trim).collect::<String>()).ok()
+		})
+		.collect()
+}
+
+/// Parse a DER SubjectPublicKeyInfo
+pub(crate) fn spki(der: &[u8]) -> Option<serde_json::Value> {
+	let (0x30, spki, _) = tlv(der)? else {
+		return None;
+	};
+	let (0x30, algorithm, rest) = tlv(spki)? else {
+		return None;
+	};
+	let (0x06, oid, params) = tlv(algorithm)? else {
+		return None;
+	};
+	let (0x03, [0, key @ ..], _) = tlv(rest)? else {
+		return None;
+	};
+	match oid {
+		RSA_OID => rsa(key),
+		EC_OID => {
+			let (0x06, curve, _) = tlv(params)? else {
+				return None;
+			};
+			let crv = match curve {
+				P256_OID => "P-256",
+				P384_OID => "P-384",
+				_ => return None,
+			};
+			// uncompressed point
+			let [4, point @ ..] = key else {
+				return None;
+			};
+			let (x, y) = point.split_at(point.len() / 2);
+			Some(serde_json::json!({
+				"kty": "EC",
+				"crv": crv,
+				"x": base64url(x),
+				"y": base64url(y),
+			}))
+		}
+		_ => None,
+	}
+}
+
+/// Parse a DER PKCS#1 RSAPublicKey
+fn rsa(der: &[u8]) -> Option<serde_json::Value> {
+	let (0x30, key, _) = tlv(der)? else {
+		return None;
+	};
+	let (0x02, n, rest) = tlv(key)? else {
+		return None;
+	};
+	let (0x02, e, _) = tlv(rest)? else {
+		return None;
+	};
+	Some(serde_json::json!({
+		"kty": "RSA",
+		"n": base64url(unsigned(n)),
+		"e": base64url(unsigned(e)),
+	}))
+}
+
+/// Split a DER element into its tag, its content and the following bytes
+pub(crate) fn tlv(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+	let (&tag, rest) = der.split_first()?;
+	let (&len, rest) = rest.split_first()?;
+	let (len, rest) = if len < 0x80 {
+		(len as usize, rest)
+	} else {
+		let count = (len & 0x7f) as usize;
+		if count == 0 || count > 4 || rest.len() < count {
+			return None;
+		}
+		let (len, rest) = rest.split_at(count);
+		(len.iter().fold(0, |acc, b| (acc << 8) | *b as usize), rest)
+	};
+	if rest.len() < len {
+		return None;
+	}
+	let (content, rest) = rest.split_at(len);
+	Some((tag, content, rest))
+}
+
+/// Remove the sign byte of a DER integer
+pub(crate) fn unsigned(int: &[u8]) -> &[u8] {
+	match int {
+		[0, rest @ ..] if !rest.is_empty() => rest,
+		_ => int,
+	}
+}
+
+pub(crate) fn base64url(bytes: &[u8]) -> String {
+	base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
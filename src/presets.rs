@@ -0,0 +1,336 @@
+use crate::{config::AuthConfig, data::Jwt, result::Result};
+
+use serde_json::Value;
+
+/// Settings of a GitLab instance validating CI job tokens (`id_tokens` or the legacy
+/// `CI_JOB_JWT`)
+#[derive(Clone, Debug)]
+pub struct Gitlab {
+	url: String,
+	legacy: bool,
+	claims: Vec<(String, Value)>,
+}
+
+/// Start the settings of the GitLab instance at the given url (eg. `https://gitlab.com`): keys
+/// from `<url>/-/jwks` and `iss` claim checked
+pub fn gitlab(instance_url: &str) -> Gitlab {
+	Gitlab {
+		url: instance_url.trim_end_matches('/').to_owned(),
+		legacy: false,
+		claims: Vec::new(),
+	}
+}
+
+impl Gitlab {
+	/// Validate the legacy `CI_JOB_JWT` tokens, whose issuer is the host of the instance
+	/// instead of its url
+	pub fn legacy_job_jwt(mut self) -> Self {
+		self.legacy = true;
+		self
+	}
+
+	/// Require the given claim value
+	fn claim(mut self, key: &str, value: &str) -> Self {
+		self.claims.push((key.to_owned(), value.into()));
+		self
+	}
+
+	/// Only accept the jobs of the given project (`group/project`)
+	pub fn project_path(self, path: &str) -> Self {
+		self.claim("project_path", path)
+	}
+
+	/// Only accept the jobs of the projects of the given group
+	pub fn namespace_path(self, path: &str) -> Self {
+		self.claim("namespace_path", path)
+	}
+
+	/// Only accept the jobs running for a protected branch or tag
+	pub fn protected_ref(self) -> Self {
+		self.claim("ref_protected", "true")
+	}
+
+	/// Only accept the jobs running for a branch
+	pub fn branch(self, name: &str) -> Self {
+		self.claim("ref_type", "branch").claim("ref", name)
+	}
+
+	/// Only accept the jobs running for a tag
+	pub fn tags(self) -> Self {
+		self.claim("ref_type", "tag")
+	}
+
+	/// Only accept the `id_tokens` issued for the given audience
+	pub fn audience(self, aud: &str) -> Self {
+		self.claim("aud", aud)
+	}
+
+	/// Url of the jwks endpoint
+	pub fn jwks(&self) -> String {
+		format!("{}/-/jwks", self.url)
+	}
+
+	/// Expected `iss` claim
+	pub fn issuer(&self) -> String {
+		if self.legacy {
+			let host = self
+				.url
+				.split_once("://")
+				.map_or(&*self.url, |(_, host)| host);
+			host.to_owned()
+		} else {
+			self.url.clone()
+		}
+	}
+
+	/// Required claims
+	pub fn claims(&self) -> Vec<(String, Value)> {
+		std::iter::once(("iss".to_owned(), self.issuer().into()))
+			.chain(self.claims.iter().cloned())
+			.collect()
+	}
+
+	/// Jwt whose keys are not loaded yet
+	pub fn unloaded(&self) -> Jwt {
+		Jwt::unloaded(&self.jwks(), self.claims())
+	}
+
+	/// Jwt with the keys of the instance
+	pub async fn load(&self) -> Result<Jwt> {
+		Jwt::with_claims(&self.jwks(), self.claims()).await
+	}
+
+	/// Configuration block, to be completed with the other options
+	pub fn config(&self) -> AuthConfig {
+		AuthConfig {
+			jwks: self.jwks(),
+			claims: self.claims(),
+			..AuthConfig::default()
+		}
+	}
+}
+
+/// Settings of an Azure AD (Entra ID) tenant
+#[derive(Clone, Debug)]
+pub struct Azure {
+	tenant: String,
+	v1: bool,
+	claims: Vec<(String, Value)>,
+}
+
+/// Start the settings of the given Azure AD tenant (id or domain), or of any tenant with
+/// `common`, `organizations` or `consumers`: v2.0 tokens with their `iss` and `ver` claims
+/// checked
+pub fn azure(tenant: &str) -> Azure {
+	Azure {
+		tenant: tenant.to_owned(),
+		v1: false,
+		claims: Vec::new(),
+	}
+}
+
+impl Azure {
+	/// Validate the v1.0 tokens (issued by `sts.windows.net`, signed by the keys of the v1.0
+	/// endpoint), still issued for the applications whose manifest doesn't request v2.0 tokens
+	pub fn v1(mut self) -> Self {
+		self.v1 = true;
+		self
+	}
+
+	/// Only accept the tokens issued for the given audience (application id or uri)
+	pub fn audience(mut self, aud: &str) -> Self {
+		self.claims.push(("aud".to_owned(), aud.into()));
+		self
+	}
+
+	/// Return true if the tenant is one of the multi-tenant aliases, in which case the issuer
+	/// depends on the tid claim of the tokens
+	fn multi_tenant(&self) -> bool {
+		matches!(&*self.tenant, "common" | "organizations" | "consumers")
+	}
+
+	/// Url of the jwks endpoint
+	pub fn jwks(&self) -> String {
+		let version = if self.v1 { "" } else { "v2.0/" };
+		format!(
+			"https://login.microsoftonline.com/{}/discovery/{}keys",
+			self.tenant, version
+		)
+	}
+
+	/// Expected `iss` claim, with a `{tenantid}` placeholder for the multi-tenant aliases
+	pub fn issuer(&self) -> String {
+		let tenant = if self.multi_tenant() {
+			"{tenantid}"
+		} else {
+			&self.tenant
+		};
+		if self.v1 {
+			format!("https://sts.windows.net/{}/", tenant)
+		} else {
+			format!("https://login.microsoftonline.com/{}/v2.0", tenant)
+		}
+	}
+
+	/// Required claims (`iss` is checked against the issuer template for the multi-tenant
+	/// aliases)
+	pub fn claims(&self) -> Vec<(String, Value)> {
+		let ver = if self.v1 { "1.0" } else { "2.0" };
+		let mut claims = vec![("ver".to_owned(), ver.into())];
+		if !self.multi_tenant() {
+			claims.push(("iss".to_owned(), self.issuer().into()));
+		}
+		claims.extend(self.claims.iter().cloned());
+		claims
+	}
+
+	/// Jwt whose keys are not loaded yet
+	pub fn unloaded(&self) -> Jwt {
+		let jwt = Jwt::unloaded(&self.jwks(), self.claims()).ignore_kid_case();
+		if self.multi_tenant() {
+			jwt.issuer_template(&self.issuer())
+		} else {
+			jwt
+		}
+	}
+
+	/// Jwt with the keys of the tenant
+	pub async fn load(&self) -> Result<Jwt> {
+		let jwt = self.unloaded();
+		jwt.set_keys().await?;
+		Ok(jwt)
+	}
+
+	/// Configuration block, to be completed with the other options
+	pub fn config(&self) -> AuthConfig {
+		AuthConfig {
+			jwks: self.jwks(),
+			claims: self.claims(),
+			issuer_template: self.multi_tenant().then(|| self.issuer()),
+			ignore_kid_case: true,
+			..AuthConfig::default()
+		}
+	}
+}
+
+/// Settings of an AWS Cognito user pool
+#[derive(Clone, Debug)]
+pub struct Cognito {
+	issuer: String,
+	token_use: &'static str,
+	client: Option<String>,
+}
+
+/// Start the settings of the given Cognito user pool (eg. `eu-west-1`, `eu-west-1_AbCdEfGhI`):
+/// access tokens with their `iss` and `token_use` claims checked
+pub fn cognito(region: &str, user_pool_id: &str) -> Cognito {
+	Cognito {
+		issuer: format!(
+			"https://cognito-idp.{}.amazonaws.com/{}",
+			region, user_pool_id
+		),
+		token_use: "access",
+		client: None,
+	}
+}
+
+impl Cognito {
+	/// Validate the ID tokens instead of the access tokens. Both are signed by different keys
+	/// of the same jwks, so only the token_use claim tells them apart
+	pub fn id_tokens(mut self) -> Self {
+		self.token_use = "id";
+		self
+	}
+
+	/// Only accept the tokens issued to the given app client, which is the `client_id` claim
+	/// of the access tokens and the `aud` claim of the ID tokens
+	pub fn client(mut self, client_id: &str) -> Self {
+		self.client = Some(client_id.to_owned());
+		self
+	}
+
+	/// Url of the jwks endpoint
+	pub fn jwks(&self) -> String {
+		format!("{}/.well-known/jwks.json", self.issuer)
+	}
+
+	/// Expected `iss` claim
+	pub fn issuer(&self) -> String {
+		self.issuer.clone()
+	}
+
+	/// Required claims
+	pub fn claims(&self) -> Vec<(String, Value)> {
+		let mut claims = vec![
+			("iss".to_owned(), self.issuer().into()),
+			("token_use".to_owned(), self.token_use.into()),
+		];
+		if let Some(client) = &self.client {
+			let key = if self.token_use == "id" {
+				"aud"
+			} else {
+				"client_id"
+			};
+			claims.push((key.to_owned(), client.as_str().into()));
+		}
+		claims
+	}
+
+	/// Jwt whose keys are not loaded yet
+	pub fn unloaded(&self) -> Jwt {
+		Jwt::unloaded(&self.jwks(), self.claims())
+	}
+
+	/// Jwt with the keys of the user pool
+	pub async fn load(&self) -> Result<Jwt> {
+		Jwt::with_claims(&self.jwks(), self.claims()).await
+	}
+
+	/// Configuration block, to be completed with the other options
+	pub fn config(&self) -> AuthConfig {
+		AuthConfig {
+			jwks: self.jwks(),
+			claims: self.claims(),
+			..AuthConfig::default()
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn gitlab_claims() {
+		let preset = gitlab("https://gitlab.example.com/").project_path("group/project");
+		assert_eq!(preset.jwks(), "https://gitlab.example.com/-/jwks");
+		assert_eq!(preset.claims()[0].1, "https://gitlab.example.com");
+		assert_eq!(preset.legacy_job_jwt().claims()[0].1, "gitlab.example.com");
+	}
+
+	#[test]
+	fn azure_claims() {
+		let preset = azure("contoso.onmicrosoft.com");
+		assert_eq!(
+			preset.jwks(),
+			"https://login.microsoftonline.com/contoso.onmicrosoft.com/discovery/v2.0/keys"
+		);
+		assert_eq!(
+			preset.v1().claims()[1].1,
+			"https://sts.windows.net/contoso.onmicrosoft.com/"
+		);
+		let preset = azure("common");
+		assert_eq!(preset.claims().len(), 1);
+		assert_eq!(
+			preset.config().issuer_template.as_deref(),
+			Some("https://login.microsoftonline.com/{tenantid}/v2.0")
+		);
+	}
+
+	#[test]
+	fn cognito_claims() {
+		let preset = cognito("eu-west-1", "eu-west-1_pool").client("app");
+		assert_eq!(preset.claims()[2].0, "client_id");
+		assert_eq!(preset.id_tokens().claims()[2].0, "aud");
+	}
+}
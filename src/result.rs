@@ -1,10 +1,37 @@
+use crate::middleware::reject::{self, ErrorFormat};
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use awc::error::SendRequestError;
 use jsonwebtoken as jwt;
 use std::str::Utf8Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Class of a failure, to tell the retryable ones from the permanent ones without matching
+/// every variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCategory {
+	/// Invalid configuration of the middleware, permanent until it is fixed
+	Config,
+	/// The keys or credentials could not be fetched or are not loaded yet, retryable
+	KeyFetch,
+	/// No token or credentials in the request
+	Missing,
+	/// Malformed token
+	TokenFormat,
+	/// The token or credentials can't be verified with a trusted key
+	Signature,
+	/// The token is genuine but its claims are expired or don't grant access
+	Claims,
+	/// Too many failures from the peer, retryable later
+	RateLimited,
+	/// Failure of a remote service (userinfo, token exchange, cache), retryable
+	Upstream,
+}
+
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
 	#[error("Failed to get JKWS from endpoint: {0}")]
 	GetError(#[source] SendRequestError),
@@ -26,4 +53,230 @@ pub enum Error {
 	ClaimNotFound(String),
 	#[error("Expected claim {0} == {1} but found {2}")]
 	Claim(String, String, String),
+	#[error("Missing bearer token")]
+	NoBearer,
+	#[error("Too many failed attempts, retry in {0} seconds")]
+	TooManyFailures(u64),
+	#[error("Missing token")]
+	NoToken,
+	#[error("Invalid token")]
+	InvalidToken,
+	#[error("Missing credentials")]
+	NoCredentials,
+	#[error("Invalid credentials")]
+	InvalidCredentials,
+	#[error("Invalid network {0}")]
+	InvalidNetwork(String),
+	#[error("Invalid secret: {0}")]
+	InvalidSecret(&'static str),
+	#[error("Failed to read token file: {0}")]
+	TokenFile(#[source] std::io::Error),
+	#[error("Failed to look up the api key: {0}")]
+	KeyStore(String),
+	#[error("Invalid configuration: {0}")]
+	Config(String),
+	#[error("Failed to refresh the keys: {0}")]
+	Refresh(String),
+	#[error("Keys are not loaded yet")]
+	KeysUnavailable,
+	#[error("Refusing to load keys over plain http from {0}")]
+	InsecureJwks(String),
+	#[error("Invalid configuration: {}", .0.join("; "))]
+	Invalid(Vec<String>),
+	#[error("Stronger authentication required: {0}")]
+	StepUp(String),
+	#[error("Untrusted issuer {0}")]
+	UntrustedIssuer(String),
+	#[error("Cannot infer the algorithm of a {0} key without alg")]
+	UnknownAlgorithm(String),
+	#[error("Untrusted jku {0}")]
+	UntrustedJku(String),
+	#[error("Untrusted x5u {0}")]
+	UntrustedX5u(String),
+	#[error("Token issued {0} seconds in the future")]
+	IssuedInFuture(u64),
+	#[error("Unsupported critical header extension {0}")]
+	UnsupportedCrit(String),
+	#[error("Token of {0} bytes is too large")]
+	TokenTooLarge(usize),
+	#[error("Failed to fetch the userinfo: {0}")]
+	Userinfo(String),
+	#[error("Token exchange failed: {0}")]
+	Exchange(String),
+	#[error("Access denied: {0}")]
+	Forbidden(String),
+	#[error("Cache error: {0}")]
+	Cache(String),
+}
+
+impl Error {
+	/// Short identifier of the error kind, usable as a metric label
+	pub fn reason(&self) -> &'static str {
+		match self {
+			Self::GetError(_)
+			| Self::BodyResponse
+			| Self::DecodeError(_)
+			| Self::DeserError(_)
+			| Self::Refresh(_)
+			| Self::KeysUnavailable
+			| Self::UnknownAlgorithm(_) => "jwks",
+			Self::JwtError(_) => "invalid_token",
+			Self::JwtHeaderError(_) | Self::UnsupportedCrit(_) => "invalid_header",
+			Self::NoKid => "no_kid",
+			Self::KeyNotFound(_) => "unknown_kid",
+			Self::ClaimNotFound(_) => "missing_claim",
+			Self::Claim(..) => "claim_mismatch",
+			Self::NoBearer | Self::NoToken | Self::NoCredentials => "no_token",
+			Self::InvalidToken | Self::InvalidCredentials | Self::TokenTooLarge(_) => "bad_token",
+			Self::InvalidNetwork(_)
+			| Self::InvalidSecret(_)
+			| Self::TokenFile(_)
+			| Self::Config(_)
+			| Self::Invalid(_)
+			| Self::InsecureJwks(_) => "config",
+			Self::TooManyFailures(_) => "rate_limited",
+			Self::KeyStore(_) => "key_store",
+			Self::StepUp(_) => "step_up",
+			Self::UntrustedIssuer(_) => "untrusted_issuer",
+			Self::UntrustedJku(_) => "untrusted_jku",
+			Self::UntrustedX5u(_) => "untrusted_x5u",
+			Self::IssuedInFuture(_) => "future_token",
+			Self::Userinfo(_) => "userinfo",
+			Self::Exchange(_) => "exchange",
+			Self::Forbidden(_) => "forbidden",
+			Self::Cache(_) => "cache",
+		}
+	}
+
+	/// Class of the error
+	pub fn category(&self) -> ErrorCategory {
+		match self {
+			Self::InvalidNetwork(_)
+			| Self::InvalidSecret(_)
+			| Self::TokenFile(_)
+			| Self::Config(_)
+			| Self::Invalid(_)
+			| Self::InsecureJwks(_)
+			| Self::UnknownAlgorithm(_) => ErrorCategory::Config,
+			Self::GetError(_)
+			| Self::BodyResponse
+			| Self::DecodeError(_)
+			| Self::DeserError(_)
+			| Self::Refresh(_)
+			| Self::KeysUnavailable
+			| Self::KeyStore(_) => ErrorCategory::KeyFetch,
+			Self::NoBearer | Self::NoToken | Self::NoCredentials => ErrorCategory::Missing,
+			Self::JwtError(e) => match e.kind() {
+				jwt::errors::ErrorKind::InvalidToken
+				| jwt::errors::ErrorKind::Base64(_)
+				| jwt::errors::ErrorKind::Json(_)
+				| jwt::errors::ErrorKind::Utf8(_) => ErrorCategory::TokenFormat,
+				jwt::errors::ErrorKind::ExpiredSignature
+				| jwt::errors::ErrorKind::ImmatureSignature
+				| jwt::errors::ErrorKind::InvalidIssuer
+				| jwt::errors::ErrorKind::InvalidAudience
+				| jwt::errors::ErrorKind::InvalidSubject
+				| jwt::errors::ErrorKind::MissingRequiredClaim(_) => ErrorCategory::Claims,
+				_ => ErrorCategory::Signature,
+			},
+			Self::JwtHeaderError(_)
+			| Self::NoKid
+			| Self::UnsupportedCrit(_)
+			| Self::TokenTooLarge(_) => ErrorCategory::TokenFormat,
+			Self::KeyNotFound(_)
+			| Self::InvalidToken
+			| Self::InvalidCredentials
+			| Self::UntrustedIssuer(_)
+			| Self::UntrustedJku(_)
+			| Self::UntrustedX5u(_) => ErrorCategory::Signature,
+			Self::ClaimNotFound(_)
+			| Self::Claim(..)
+			| Self::StepUp(_)
+			| Self::IssuedInFuture(_)
+			| Self::Forbidden(_) => ErrorCategory::Claims,
+			Self::TooManyFailures(_) => ErrorCategory::RateLimited,
+			Self::Userinfo(_) | Self::Exchange(_) | Self::Cache(_) => ErrorCategory::Upstream,
+		}
+	}
+
+	/// Return true if the same request may succeed later without any change
+	pub fn is_retryable(&self) -> bool {
+		matches!(
+			self.category(),
+			ErrorCategory::KeyFetch | ErrorCategory::RateLimited | ErrorCategory::Upstream
+		)
+	}
+
+	/// Kind of the underlying jsonwebtoken error if any
+	pub fn jwt_kind(&self) -> Option<&jwt::errors::ErrorKind> {
+		match self {
+			Self::JwtError(e) | Self::JwtHeaderError(e) => Some(e.kind()),
+			_ => None,
+		}
+	}
+}
+
+/// Responses of the handlers calling validate_jwt (or other fallible functions of the crate)
+/// directly with `?`: rejected tokens get the same answer as with the middlewares, while
+/// failures of the service itself (configuration, jwks endpoint, stores) are logged and
+/// answered without details
+impl ResponseError for Error {
+	fn status_code(&self) -> StatusCode {
+		match self {
+			Self::InvalidNetwork(_)
+			| Self::InvalidSecret(_)
+			| Self::TokenFile(_)
+			| Self::Config(_)
+			| Self::Invalid(_)
+			| Self::InsecureJwks(_)
+			| Self::UnknownAlgorithm(_)
+			| Self::KeyStore(_)
+			| Self::Cache(_) => StatusCode::INTERNAL_SERVER_ERROR,
+			Self::GetError(_)
+			| Self::BodyResponse
+			| Self::DecodeError(_)
+			| Self::DeserError(_)
+			| Self::Refresh(_) => StatusCode::SERVICE_UNAVAILABLE,
+			Self::Exchange(_) => StatusCode::BAD_GATEWAY,
+			_ => reject::status(self),
+		}
+	}
+
+	fn error_response(&self) -> HttpResponse {
+		let status = self.status_code();
+		if status == reject::status(self) {
+			reject::response(self, ErrorFormat::default(), None)
+		} else {
+			tracing::error!("{}", self);
+			HttpResponse::build(status)
+				.content_type("text/plain; charset=utf-8")
+				.body(status.canonical_reason().unwrap_or_default())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn response_status() {
+		assert_eq!(Error::NoBearer.status_code(), StatusCode::UNAUTHORIZED);
+		assert_eq!(
+			Error::Forbidden("alice GET /".to_owned()).status_code(),
+			StatusCode::FORBIDDEN
+		);
+		let res = Error::Config("no jwks".to_owned()).error_response();
+		assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+		assert!(res.headers().get("www-authenticate").is_none());
+	}
+
+	#[test]
+	fn categories() {
+		let expired = Error::JwtError(jwt::errors::ErrorKind::ExpiredSignature.into());
+		assert_eq!(expired.category(), ErrorCategory::Claims);
+		assert!(!expired.is_retryable());
+		assert_eq!(Error::KeysUnavailable.category(), ErrorCategory::KeyFetch);
+		assert!(Error::KeysUnavailable.is_retryable());
+	}
 }
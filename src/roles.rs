@@ -0,0 +1,98 @@
+use crate::middleware::chain::AuthContext;
+
+use actix_utils::future::{ready, Ready};
+use actix_web::{
+	dev::Payload, error::ErrorUnauthorized, Error, FromRequest, HttpMessage, HttpRequest,
+};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// Flat set of the roles granted by a Keycloak token: realm roles (`realm_access.roles`) as is,
+/// client roles (`resource_access.<client>.roles`) as `<client>:<role>`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Roles(BTreeSet<String>);
+
+impl Roles {
+	/// Roles of the claims of a Keycloak token
+	pub fn keycloak(claims: &Value) -> Self {
+		let mut roles = Self::realm(claims);
+		if let Some(clients) = claims.get("resource_access").and_then(Value::as_object) {
+			for (client, access) in clients {
+				roles
+					.0
+					.extend(names(access.get("roles")).map(|role| format!("{}:{}", client, role)));
+			}
+		}
+		roles
+	}
+
+	/// Realm roles and roles of the given client (without prefix) of a Keycloak token
+	pub fn keycloak_client(claims: &Value, client: &str) -> Self {
+		let mut roles = Self::realm(claims);
+		let access = claims
+			.get("resource_access")
+			.and_then(|access| access.get(client));
+		roles
+			.0
+			.extend(names(access.and_then(|access| access.get("roles"))).map(str::to_owned));
+		roles
+	}
+
+	/// Realm roles of a Keycloak token
+	fn realm(claims: &Value) -> Self {
+		let roles = claims
+			.get("realm_access")
+			.and_then(|access| access.get("roles"));
+		Self(names(roles).map(str::to_owned).collect())
+	}
+
+	/// Return true if the role is granted
+	pub fn contains(&self, role: &str) -> bool {
+		self.0.contains(role)
+	}
+
+	/// Granted roles
+	pub fn iter(&self) -> impl Iterator<Item = &str> {
+		self.0.iter().map(String::as_str)
+	}
+}
+
+/// Role names of a json array
+fn names(roles: Option<&Value>) -> impl Iterator<Item = &str> {
+	roles
+		.and_then(Value::as_array)
+		.into_iter()
+		.flatten()
+		.filter_map(Value::as_str)
+}
+
+/// Extract the Keycloak roles of the token validated by `JwtAuth`. Requests without one are
+/// rejected with 401
+impl FromRequest for Roles {
+	type Error = Error;
+	type Future = Ready<Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+		let roles = match req.extensions().get::<AuthContext>() {
+			Some(AuthContext::Jwt(claims)) => Some(Roles::keycloak(claims)),
+			_ => None,
+		};
+		ready(roles.ok_or_else(|| ErrorUnauthorized("not authorized")))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn keycloak_roles() {
+		let claims = serde_json::json!({
+			"realm_access": { "roles": ["user"] },
+			"resource_access": { "api": { "roles": ["admin"] } },
+		});
+		let roles = Roles::keycloak(&claims);
+		assert!(roles.contains("user") && roles.contains("api:admin"));
+		assert!(Roles::keycloak_client(&claims, "api").contains("admin"));
+	}
+}
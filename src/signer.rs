@@ -0,0 +1,150 @@
+use crate::{
+	config,
+	data::Jwt,
+	keys::thumbprint,
+	result::{Error, Result},
+};
+
+use actix_web::{web, HttpResponse};
+use jsonwebkey as jwk;
+use jsonwebtoken as jwt;
+use serde_json::{json, Value};
+use std::{
+	str::FromStr,
+	time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Mint tokens with a private key, for services issuing service-to-service tokens with the
+/// same crate they validate tokens with
+#[derive(Clone)]
+pub struct Signer {
+	key: jwk::JsonWebKey,
+	header: jwt::Header,
+	encoding: jwt::EncodingKey,
+	claims: Vec<(String, Value)>,
+	ttl: Duration,
+}
+
+impl Signer {
+	/// Sign with the given private JWK, which must have an alg. The kid defaults to the RFC 7638
+	/// thumbprint of the key
+	pub fn from_jwk(key: &str) -> Result<Self> {
+		let mut key = jwk::JsonWebKey::from_str(key)
+			.map_err(|e| Error::Config(format!("invalid signing key: {}", e)))?;
+		let alg = key
+			.algorithm
+			.ok_or_else(|| Error::Config("the signing key has no alg".to_owned()))?;
+		if !key.key.is_private() {
+			return Err(Error::Config("the signing key is public".to_owned()));
+		}
+		if key.key_id.is_none() {
+			key.key_id = thumbprint(&key);
+		}
+		let mut header = jwt::Header::new(alg.into());
+		header.kid = key.key_id.clone();
+		Ok(Self {
+			encoding: key.key.to_encoding_key(),
+			key,
+			header,
+			claims: Vec::new(),
+			ttl: Duration::from_secs(300),
+		})
+	}
+
+	/// Same as from_jwk with the key read from the given environment variable
+	pub fn from_env(name: &str) -> Result<Self> {
+		Self::from_jwk(&config::required_var(name)?)
+	}
+
+	/// Add the claim `key` with the given value to the tokens not setting it (eg. `iss`, `aud`)
+	pub fn claim(self, key: &str, value: &str) -> Self {
+		self.claim_value(key, value.into())
+	}
+
+	/// Same as claim with a json value
+	pub fn claim_value(mut self, key: &str, value: Value) -> Self {
+		self.claims.push((key.to_owned(), value));
+		self
+	}
+
+	/// Lifetime of the tokens (5 minutes by default)
+	pub fn ttl(mut self, ttl: Duration) -> Self {
+		self.ttl = ttl;
+		self
+	}
+
+	/// Lifetime of the tokens
+	pub(crate) fn lifetime(&self) -> Duration {
+		self.ttl
+	}
+
+	/// Key id set in the header of the tokens
+	pub fn kid(&self) -> &str {
+		self.header.kid.as_deref().unwrap_or_default()
+	}
+
+	/// Public part of the key, as published in a JWKS (None for a symmetric key)
+	pub fn public(&self) -> Option<jwk::JsonWebKey> {
+		let mut public = self.key.clone();
+		public.key = Box::new(self.key.key.to_public()?.into_owned());
+		Some(public)
+	}
+
+	/// JWKS document publishing the public part of the key
+	pub fn jwks(&self) -> Value {
+		json!({ "keys": self.public().into_iter().collect::<Vec<_>>() })
+	}
+
+	/// Jwt validating the tokens of this signer (and checking its claims)
+	pub fn verifier(&self) -> Jwt {
+		let key = self.public().unwrap_or_else(|| self.key.clone());
+		Jwt::from_keys(vec![key], self.claims.clone())
+	}
+
+	/// Sign the given claims (an object) after setting `iat`, `exp` from the ttl and the
+	/// configured claims they don't set
+	pub fn sign(&self, claims: &Value) -> Result<String> {
+		let mut claims = claims.clone();
+		let object = claims
+			.as_object_mut()
+			.ok_or_else(|| Error::Config("the claims must be an object".to_owned()))?;
+		for (key, value) in &self.claims {
+			object.entry(key.as_str()).or_insert_with(|| value.clone());
+		}
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or_default();
+		object.insert("iat".to_owned(), now.into());
+		object.insert("exp".to_owned(), (now + self.ttl.as_secs()).into());
+		jwt::encode(&self.header, &claims, &self.encoding).map_err(Error::JwtError)
+	}
+}
+
+/// Handler publishing the JWKS of a `Data<Signer>` in the app data
+///
+/// ```ignore
+/// App::new()
+///     .app_data(web::Data::new(signer))
+///     .route("/.well-known/jwks.json", web::get().to(jwks))
+/// ```
+pub async fn jwks(signer: web::Data<Signer>) -> HttpResponse {
+	HttpResponse::Ok()
+		.content_type("application/json")
+		.body(signer.jwks().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sign_and_verify() {
+		let key = include_str!("../tests/fixtures/signing.json");
+		let signer = Signer::from_jwk(key).unwrap().claim("iss", "service");
+		assert_eq!(signer.jwks()["keys"][0].get("d"), None);
+		let token = signer.sign(&json!({ "sub": "worker" })).unwrap();
+		let claims = signer.verifier().validate_jwt(&token).unwrap().claims;
+		assert_eq!(claims["iss"], "service");
+	}
+}
@@ -0,0 +1,135 @@
+use actix_web::{dev::ServerHandle, rt, web, App, HttpResponse, HttpServer};
+use jsonwebkey as jwk;
+use jsonwebtoken as jwt;
+use serde_json::{json, Value};
+use std::{
+	io,
+	sync::{Arc, RwLock},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Signing key generated for tests, to mint tokens without a live identity provider (P-256
+/// with ES256, as jsonwebkey can't generate RSA keys)
+#[derive(Clone, Debug)]
+pub struct TestKey(jwk::JsonWebKey);
+
+impl TestKey {
+	/// Generate a new key with the given kid
+	pub fn es256(kid: &str) -> Self {
+		let mut key = jwk::JsonWebKey::new(jwk::Key::generate_p256());
+		key.set_algorithm(jwk::Algorithm::ES256)
+			.expect("ES256 is valid for a P-256 key");
+		key.key_id = Some(kid.to_owned());
+		Self(key)
+	}
+
+	/// Return the key id
+	pub fn kid(&self) -> &str {
+		self.0.key_id.as_deref().unwrap_or_default()
+	}
+
+	/// Return the public part of the key, as published in a JWKS
+	pub fn public(&self) -> jwk::JsonWebKey {
+		let mut public = self.0.clone();
+		public.key = Box::new(
+			self.0
+				.key
+				.to_public()
+				.expect("a P-256 key has a public part")
+				.into_owned(),
+		);
+		public
+	}
+
+	/// Sign the given claims as is
+	pub fn sign(&self, claims: &Value) -> String {
+		let mut header = jwt::Header::new(jwt::Algorithm::ES256);
+		header.kid = self.0.key_id.clone();
+		jwt::encode(&header, claims, &self.0.key.to_encoding_key())
+			.expect("claims are serializable")
+	}
+
+	/// Sign the given claims (an object) after setting `iat` to now and `exp` to now plus the
+	/// given number of seconds (negative for an expired token)
+	pub fn mint(&self, mut claims: Value, expires_in: i64) -> String {
+		let now = now();
+		claims["iat"] = now.into();
+		claims["exp"] = (now + expires_in).into();
+		self.sign(&claims)
+	}
+}
+
+/// Build the JWKS document publishing the public part of the given keys
+pub fn jwks(keys: &[&TestKey]) -> String {
+	let keys = keys.iter().map(|key| key.public()).collect::<Vec<_>>();
+	json!({ "keys": keys }).to_string()
+}
+
+/// Current time in seconds since the epoch
+fn now() -> i64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs() as i64)
+		.unwrap_or_default()
+}
+
+/// In-process http server publishing the JWKS of test keys, which can be rotated on demand to
+/// test the refresh of the keys end to end
+pub struct JwksServer {
+	url: String,
+	keys: Arc<RwLock<Vec<TestKey>>>,
+	handle: ServerHandle,
+}
+
+impl JwksServer {
+	/// Start serving the JWKS of the given key on a random local port
+	pub async fn start(key: TestKey) -> io::Result<Self> {
+		let keys = Arc::new(RwLock::new(vec![key]));
+		let data = web::Data::from(keys.clone());
+		let server = HttpServer::new(move || {
+			App::new().app_data(data.clone()).route(
+				"/jwks",
+				web::get().to(|keys: web::Data<RwLock<Vec<TestKey>>>| async move {
+					let keys = keys.read().unwrap();
+					HttpResponse::Ok()
+						.content_type("application/json")
+						.body(jwks(&keys.iter().collect::<Vec<_>>()))
+				}),
+			)
+		})
+		.workers(1)
+		.bind(("127.0.0.1", 0))?;
+		let url = format!("http://{}/jwks", server.addrs()[0]);
+		let server = server.run();
+		let handle = server.handle();
+		rt::spawn(server);
+		Ok(Self { url, keys, handle })
+	}
+
+	/// Url of the JWKS (plain http, so the Jwt must allow insecure jwks)
+	pub fn url(&self) -> &str {
+		&self.url
+	}
+
+	/// Return the most recently published key
+	pub fn key(&self) -> TestKey {
+		self.keys.read().unwrap().last().cloned().unwrap()
+	}
+
+	/// Publish an additional key (eg. the next one before a rotation)
+	pub fn publish(&self, key: TestKey) {
+		self.keys.write().unwrap().push(key);
+	}
+
+	/// Replace the published keys with a new one and return it
+	pub fn rotate(&self, kid: &str) -> TestKey {
+		let key = TestKey::es256(kid);
+		*self.keys.write().unwrap() = vec![key.clone()];
+		key
+	}
+
+	/// Stop the server
+	pub async fn stop(self) {
+		self.handle.stop(true).await;
+	}
+}
@@ -0,0 +1,901 @@
+use crate::{
+	cache::{Cache, MemoryCache},
+	result::{Error, Result},
+};
+
+use actix_web::web;
+use futures_util::{future::LocalBoxFuture, FutureExt};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::HashMap,
+	fmt, fs,
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::{
+		atomic::{AtomicU64, Ordering},
+		Arc, Mutex, RwLock,
+	},
+	time::{Duration, Instant, SystemTime},
+};
+use subtle::ConstantTimeEq;
+
+// minimum delay between two checks of a token file
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// number of cached lookups above which expired entries are purged
+const CACHE_CAPACITY: usize = 10_000;
+
+// default freshness window of hmac timestamped tokens
+const HMAC_WINDOW: Duration = Duration::from_secs(300);
+
+// number of accepted hmac values remembered in memory
+const REPLAY_CAPACITY: usize = 10_000;
+
+/// A static secret, stored in plain text or as a hash so that the plain text never lives in the
+/// server configuration
+#[derive(Clone, PartialEq, Eq)]
+pub enum Secret {
+	/// Plain text secret
+	Plain(String),
+	/// SHA-256 digest of the secret
+	Sha256([u8; 32]),
+	/// Argon2 PHC string (`$argon2id$v=19$...`). Verification is deliberately slow, so in a
+	/// `TokenSet` clients present `<client name>.<secret>` and only the secret of that client is
+	/// checked
+	#[cfg(feature = "argon2")]
+	Argon2(String),
+	/// Bcrypt hash (`$2y$...`), as found in htpasswd files. Presented like argon2 secrets in a
+	/// `TokenSet`
+	#[cfg(feature = "bcrypt")]
+	Bcrypt(String),
+	/// Shared HMAC-SHA256 key. Clients present `<unix timestamp>.<hex signature of the timestamp>`
+	/// which is only accepted within the freshness window. A `TokenSet` accepts each value once,
+	/// so that a sniffed value can't be replayed
+	Hmac { key: Vec<u8>, window: Duration },
+}
+
+impl Secret {
+	/// Hash a plain text secret with SHA-256
+	pub fn sha256(plain: &str) -> Self {
+		Self::Sha256(Sha256::digest(plain.as_bytes()).into())
+	}
+
+	/// Shared HMAC key accepting timestamps within the given window (in both directions to
+	/// account for clock skew)
+	pub fn hmac(key: &[u8], window: Duration) -> Self {
+		Self::Hmac {
+			key: key.to_owned(),
+			window,
+		}
+	}
+
+	/// Compute the value a client presents at the given time with the HMAC key
+	pub fn sign(key: &[u8], timestamp: SystemTime) -> String {
+		let timestamp = timestamp
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs()
+			.to_string();
+		let signature = Hmac::<Sha256>::new_from_slice(key)
+			.expect("hmac accepts keys of any size")
+			.chain_update(timestamp.as_bytes())
+			.finalize()
+			.into_bytes();
+		let hex = signature
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect::<String>();
+		format!("{}.{}", timestamp, hex)
+	}
+
+	/// Parse an argon2 PHC string
+	#[cfg(feature = "argon2")]
+	fn argon2(phc: &str) -> Result<Self> {
+		argon2::PasswordHash::new(phc).map_err(|_| Error::InvalidSecret("invalid argon2 hash"))?;
+		Ok(Self::Argon2(phc.to_owned()))
+	}
+
+	#[cfg(not(feature = "argon2"))]
+	fn argon2(_phc: &str) -> Result<Self> {
+		Err(Error::InvalidSecret("argon2 feature is not enabled"))
+	}
+
+	/// Parse a bcrypt hash
+	#[cfg(feature = "bcrypt")]
+	fn bcrypt(hash: &str) -> Result<Self> {
+		Ok(Self::Bcrypt(hash.to_owned()))
+	}
+
+	#[cfg(not(feature = "bcrypt"))]
+	fn bcrypt(_hash: &str) -> Result<Self> {
+		Err(Error::InvalidSecret("bcrypt feature is not enabled"))
+	}
+
+	/// Return true if the verification is deliberately slow (argon2 and bcrypt)
+	#[allow(clippy::match_single_binding)] // both hash features may be disabled
+	pub(crate) fn is_slow(&self) -> bool {
+		match self {
+			#[cfg(feature = "argon2")]
+			Self::Argon2(_) => true,
+			#[cfg(feature = "bcrypt")]
+			Self::Bcrypt(_) => true,
+			_ => false,
+		}
+	}
+
+	/// Check in constant time that the presented value matches the secret
+	pub fn verify(&self, presented: &str) -> bool {
+		match self {
+			Self::Plain(secret) => presented.as_bytes().ct_eq(secret.as_bytes()).into(),
+			Self::Sha256(digest) => Sha256::digest(presented.as_bytes())
+				.as_slice()
+				.ct_eq(digest)
+				.into(),
+			#[cfg(feature = "argon2")]
+			Self::Argon2(phc) => {
+				use argon2::{Argon2, PasswordHash, PasswordVerifier};
+				PasswordHash::new(phc)
+					.map(|hash| {
+						Argon2::default()
+							.verify_password(presented.as_bytes(), &hash)
+							.is_ok()
+					})
+					.unwrap_or(false)
+			}
+			#[cfg(feature = "bcrypt")]
+			Self::Bcrypt(hash) => bcrypt::verify(presented, hash).unwrap_or(false),
+			Self::Hmac { key, window } => verify_hmac(key, *window, presented),
+		}
+	}
+}
+
+/// Check the signature of a timestamped value and that the timestamp is fresh
+fn verify_hmac(key: &[u8], window: Duration, presented: &str) -> bool {
+	let Some((timestamp, signature)) = presented.split_once('.') else {
+		return false;
+	};
+	let Some(signature) = hex(signature) else {
+		return false;
+	};
+	let Ok(secs) = timestamp.parse::<u64>() else {
+		return false;
+	};
+	let now = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs();
+	if now.abs_diff(secs) > window.as_secs() {
+		return false;
+	}
+	Hmac::<Sha256>::new_from_slice(key)
+		.expect("hmac accepts keys of any size")
+		.chain_update(timestamp.as_bytes())
+		.verify_slice(&signature)
+		.is_ok()
+}
+
+/// Decode an hexadecimal string
+fn hex(s: &str) -> Option<Vec<u8>> {
+	if s.len() % 2 != 0 {
+		return None;
+	}
+	(0..s.len())
+		.step_by(2)
+		.map(|i| {
+			s.get(i..i + 2)
+				.and_then(|byte| u8::from_str_radix(byte, 16).ok())
+		})
+		.collect()
+}
+
+impl FromStr for Secret {
+	type Err = Error;
+
+	/// Parse `sha256:<hex digest>`, `hmac:<key>`, an argon2 PHC string, a bcrypt hash, or a plain
+	/// text secret
+	fn from_str(s: &str) -> Result<Self> {
+		if let Some(digest) = s.strip_prefix("sha256:") {
+			let bytes =
+				hex(digest).ok_or(Error::InvalidSecret("sha256 digest must be hexadecimal"))?;
+			let digest = bytes
+				.try_into()
+				.map_err(|_| Error::InvalidSecret("sha256 digest must be 32 bytes long"))?;
+			Ok(Self::Sha256(digest))
+		} else if let Some(key) = s.strip_prefix("hmac:") {
+			Ok(Self::hmac(key.as_bytes(), HMAC_WINDOW))
+		} else if s.starts_with("$argon2") {
+			Self::argon2(s)
+		} else if ["$2a$", "$2b$", "$2y$"]
+			.iter()
+			.any(|prefix| s.starts_with(prefix))
+		{
+			Self::bcrypt(s)
+		} else {
+			Ok(Self::Plain(s.to_owned()))
+		}
+	}
+}
+
+impl fmt::Debug for Secret {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		// never print the secret itself
+		match self {
+			Self::Plain(_) => write!(f, "Plain(***)"),
+			Self::Sha256(_) => write!(f, "Sha256(***)"),
+			#[cfg(feature = "argon2")]
+			Self::Argon2(_) => write!(f, "Argon2(***)"),
+			#[cfg(feature = "bcrypt")]
+			Self::Bcrypt(_) => write!(f, "Bcrypt(***)"),
+			Self::Hmac { window, .. } => write!(f, "Hmac(***, {:?})", window),
+		}
+	}
+}
+
+/// Client a static token was issued to. The identity of the matching token is inserted in the
+/// request extensions
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenIdentity {
+	/// Name of the client (empty if not configured)
+	pub name: String,
+	/// Scopes granted to the client
+	#[serde(default)]
+	pub scopes: Vec<String>,
+	/// Free form description
+	#[serde(default)]
+	pub description: Option<String>,
+}
+
+impl TokenIdentity {
+	/// Construct an identity with the given client name
+	pub fn new(name: &str) -> Self {
+		Self {
+			name: name.to_owned(),
+			..Self::default()
+		}
+	}
+
+	/// Add a scope
+	pub fn scope(mut self, scope: &str) -> Self {
+		self.scopes.push(scope.to_owned());
+		self
+	}
+
+	/// Set the description
+	pub fn description(mut self, description: &str) -> Self {
+		self.description = Some(description.to_owned());
+		self
+	}
+
+	/// Return true if the scope was granted
+	pub fn has_scope(&self, scope: &str) -> bool {
+		self.scopes.iter().any(|s| s == scope)
+	}
+}
+
+/// Usage of a token, to spot stale credentials before rotating them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+	/// Identity of the client owning the token
+	pub identity: TokenIdentity,
+	/// Number of accepted requests
+	pub uses: u64,
+	/// Time of the last accepted request
+	pub last_used: Option<SystemTime>,
+	/// Time after which the token is rejected
+	pub expires: Option<SystemTime>,
+}
+
+/// A secret accepted until an optional expiry
+#[derive(Debug)]
+pub(crate) struct Token {
+	secret: Secret,
+	identity: TokenIdentity,
+	expires: Option<SystemTime>,
+	uses: AtomicU64,
+	// seconds since the epoch, 0 if never used
+	last_used: AtomicU64,
+	// loaded from a token file, and kept revoked until it disappears from the file
+	file: bool,
+	revoked: bool,
+}
+
+impl Token {
+	/// Return true if the token is neither expired nor revoked
+	fn live(&self, now: SystemTime) -> bool {
+		!self.revoked && self.expires.map_or(true, |expires| now < expires)
+	}
+
+	/// Return the part of the presented value to check against the secret. Slow hashes are
+	/// indexed by the name of their client (`<name>.<secret>`) so that a request costs a single
+	/// slow verification, and are never candidates without a name
+	fn candidate<'a>(&self, presented: &'a str) -> Option<&'a str> {
+		if !self.secret.is_slow() {
+			return Some(presented);
+		}
+		let (name, secret) = presented.split_once('.')?;
+		(!self.identity.name.is_empty() && name == self.identity.name).then_some(secret)
+	}
+
+	/// Return true if the token is not expired and matches the presented value
+	fn verify(&self, presented: &str, now: SystemTime) -> bool {
+		self.live(now)
+			&& self
+				.candidate(presented)
+				.map_or(false, |presented| self.secret.verify(presented))
+	}
+
+	/// Record an accepted request
+	fn used(&self, now: SystemTime) {
+		self.uses.fetch_add(1, Ordering::Relaxed);
+		let secs = now
+			.duration_since(SystemTime::UNIX_EPOCH)
+			.unwrap_or_default()
+			.as_secs();
+		self.last_used.store(secs, Ordering::Relaxed);
+	}
+
+	/// Return the usage of the token
+	fn usage(&self) -> TokenUsage {
+		let last_used = self.last_used.load(Ordering::Relaxed);
+		TokenUsage {
+			identity: self.identity.clone(),
+			uses: self.uses.load(Ordering::Relaxed),
+			last_used: (last_used != 0)
+				.then(|| SystemTime::UNIX_EPOCH + Duration::from_secs(last_used)),
+			expires: self.expires,
+		}
+	}
+}
+
+impl From<Secret> for Token {
+	fn from(secret: Secret) -> Self {
+		(secret, TokenIdentity::default()).into()
+	}
+}
+
+impl From<(Secret, TokenIdentity)> for Token {
+	fn from((secret, identity): (Secret, TokenIdentity)) -> Self {
+		Self {
+			secret,
+			identity,
+			expires: None,
+			uses: AtomicU64::new(0),
+			last_used: AtomicU64::new(0),
+			file: false,
+			revoked: false,
+		}
+	}
+}
+
+/// Set of secrets shared by all the clones of a TokenAuth. It can be registered as
+/// `Data<TokenSet>` so that an admin endpoint can add or revoke tokens at runtime
+#[derive(Clone)]
+pub struct TokenSet {
+	tokens: Arc<RwLock<Vec<Token>>>,
+	// hmac values already accepted, until they leave the freshness window
+	replay: Arc<dyn Cache + Send + Sync>,
+}
+
+impl Default for TokenSet {
+	fn default() -> Self {
+		Self {
+			tokens: Arc::default(),
+			replay: Arc::new(MemoryCache::new(REPLAY_CAPACITY)),
+		}
+	}
+}
+
+impl fmt::Debug for TokenSet {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.debug_tuple("TokenSet").field(&self.tokens).finish()
+	}
+}
+
+impl TokenSet {
+	/// Construct a set from the given secrets
+	pub fn new<I>(secrets: I) -> Self
+	where
+		I: IntoIterator<Item = Secret>,
+	{
+		Self::with_identities(
+			secrets
+				.into_iter()
+				.map(|secret| (secret, TokenIdentity::default())),
+		)
+	}
+
+	/// Construct a set from the given secrets and the identities of their clients
+	pub fn with_identities<I>(tokens: I) -> Self
+	where
+		I: IntoIterator<Item = (Secret, TokenIdentity)>,
+	{
+		Self {
+			tokens: Arc::new(RwLock::new(tokens.into_iter().map(Token::from).collect())),
+			..Self::default()
+		}
+	}
+
+	/// Remember the accepted hmac values in the given cache (eg. shared by the replicas) instead
+	/// of in memory
+	pub fn replay_cache<C>(mut self, cache: C) -> Self
+	where
+		C: Cache + Send + Sync + 'static,
+	{
+		self.replay = Arc::new(cache);
+		self
+	}
+
+	/// Return true if the presented hmac value is used for the first time. It is remembered
+	/// twice the window, the time it takes for its timestamp to become stale in any case
+	async fn first_use(&self, presented: &str, window: Duration) -> Result<bool> {
+		let digest = Sha256::digest(presented.as_bytes())
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect::<String>();
+		self.replay
+			.set_new(&format!("hmac:{}", digest), Vec::new(), window * 2)
+			.await
+	}
+
+	/// Return true if the presented value matches one of the secrets
+	pub fn verify(&self, presented: &str) -> bool {
+		self.identify(presented).is_some()
+	}
+
+	/// Return the identity of the secret matching the presented value. Slow hashes are verified
+	/// on the calling thread, and hmac values are rejected if the replay cache can't answer
+	/// right away: servers should go through `ApiKeyStore::lookup` instead
+	pub fn identify(&self, presented: &str) -> Option<TokenIdentity> {
+		let now = SystemTime::now();
+		let tokens = self.tokens.read().unwrap();
+		let token = tokens.iter().find(|token| token.verify(presented, now))?;
+		if let Secret::Hmac { window, .. } = &token.secret {
+			let first_use = self.first_use(presented, *window).now_or_never();
+			if !matches!(first_use, Some(Ok(true))) {
+				return None;
+			}
+		}
+		token.used(now);
+		Some(token.identity.clone())
+	}
+
+	/// Return the usage of every token
+	pub fn usage(&self) -> Vec<TokenUsage> {
+		self.tokens
+			.read()
+			.unwrap()
+			.iter()
+			.filter(|token| !token.revoked)
+			.map(Token::usage)
+			.collect()
+	}
+
+	/// Accept a new secret
+	pub fn add(&self, secret: Secret, identity: TokenIdentity) {
+		self.tokens.write().unwrap().push((secret, identity).into());
+	}
+
+	/// Accept a new secret until the given time (eg. for temporary credentials handed to a
+	/// contractor or a CI job)
+	pub fn add_until(&self, secret: Secret, identity: TokenIdentity, expires: SystemTime) {
+		let mut token = Token::from((secret, identity));
+		token.expires = Some(expires);
+		self.tokens.write().unwrap().push(token);
+	}
+
+	/// Forget the expired secrets (they are rejected anyway). Those of a token file are kept
+	/// until they disappear from the file, so that a reload doesn't accept them again
+	pub fn purge(&self) {
+		let now = SystemTime::now();
+		self.tokens
+			.write()
+			.unwrap()
+			.retain(|token| token.file || token.expires.map_or(true, |expires| expires > now));
+	}
+
+	/// Stop accepting the secrets matching the presented value immediately and return true if
+	/// any was removed. Secrets of a token file stay revoked when the file is reloaded
+	pub fn revoke(&self, presented: &str) -> bool {
+		self.revoke_if(|token| {
+			token
+				.candidate(presented)
+				.map_or(false, |presented| token.secret.verify(presented))
+		})
+	}
+
+	/// Stop accepting the secrets of the named client and return true if any was removed
+	pub fn revoke_client(&self, name: &str) -> bool {
+		self.revoke_if(|token| token.identity.name == name)
+	}
+
+	/// Remove the runtime tokens matching the predicate and flag the ones of the token file
+	fn revoke_if<F: Fn(&Token) -> bool>(&self, revoked: F) -> bool {
+		let mut tokens = self.tokens.write().unwrap();
+		let mut found = false;
+		tokens.retain_mut(|token| {
+			if token.revoked || !revoked(token) {
+				return true;
+			}
+			found = true;
+			token.revoked = true;
+			token.file
+		});
+		found
+	}
+
+	/// Accept the new secret of the named client, with the identity of its current secrets, and
+	/// keep accepting the current ones during the grace period only. The secrets of the token
+	/// file are rotated by changing the file
+	pub fn rotate(&self, name: &str, secret: Secret, grace: Duration) {
+		let now = SystemTime::now();
+		let deadline = now + grace;
+		let mut tokens = self.tokens.write().unwrap();
+		let rotated = |token: &Token| !token.file && token.identity.name == name;
+		tokens
+			.retain(|token| !rotated(token) || token.expires.map_or(true, |expires| expires > now));
+		let identity = tokens
+			.iter()
+			.rev()
+			.find(|token| rotated(token) && token.live(now))
+			.map_or_else(|| TokenIdentity::new(name), |token| token.identity.clone());
+		for token in tokens.iter_mut().filter(|token| rotated(token)) {
+			token.expires = Some(
+				token
+					.expires
+					.map_or(deadline, |expires| expires.min(deadline)),
+			);
+		}
+		tokens.push((secret, identity).into());
+	}
+
+	/// Replace the secrets of the token file, keeping the tokens added at runtime. The entries
+	/// that didn't change keep their usage, expiry and revocation
+	pub(crate) fn merge_file(&self, secrets: Vec<(Secret, TokenIdentity)>) {
+		let mut tokens = self.tokens.write().unwrap();
+		let (mut previous, runtime): (Vec<_>, Vec<_>) =
+			tokens.drain(..).partition(|token| token.file);
+		*tokens = runtime;
+		for (secret, identity) in secrets {
+			match previous
+				.iter()
+				.position(|token| token.secret == secret && token.identity == identity)
+			{
+				Some(i) => tokens.push(previous.swap_remove(i)),
+				None => {
+					let mut token = Token::from((secret, identity));
+					token.file = true;
+					tokens.push(token);
+				}
+			}
+		}
+	}
+}
+
+/// Asynchronous lookup of api keys in an external store (database, cache...)
+pub trait ApiKeyStore {
+	/// Return the identity of the client owning the presented key, or None if the key is
+	/// unknown. Errors reject the request
+	fn lookup<'a>(
+		&'a self,
+		presented: &'a str,
+	) -> LocalBoxFuture<'a, Result<Option<TokenIdentity>>>;
+}
+
+impl ApiKeyStore for TokenSet {
+	fn lookup<'a>(
+		&'a self,
+		presented: &'a str,
+	) -> LocalBoxFuture<'a, Result<Option<TokenIdentity>>> {
+		Box::pin(async move {
+			let now = SystemTime::now();
+			// fast secrets are checked in place, and at most one slow hash is selected by name
+			let found = {
+				let tokens = self.tokens.read().unwrap();
+				match tokens
+					.iter()
+					.find(|token| !token.secret.is_slow() && token.verify(presented, now))
+				{
+					Some(token) if !matches!(token.secret, Secret::Hmac { .. }) => {
+						token.used(now);
+						return Ok(Some(token.identity.clone()));
+					}
+					Some(token) => Some((token.secret.clone(), token.identity.clone(), None)),
+					None => tokens.iter().find_map(|token| {
+						let candidate = token.candidate(presented)?;
+						(token.secret.is_slow() && token.live(now)).then(|| {
+							(
+								token.secret.clone(),
+								token.identity.clone(),
+								Some(candidate.to_owned()),
+							)
+						})
+					}),
+				}
+			};
+			let Some((secret, identity, candidate)) = found else {
+				return Ok(None);
+			};
+			let secret = match candidate {
+				Some(candidate) => {
+					// keep the workers responsive during the verification
+					let (secret, valid) = web::block(move || {
+						let valid = secret.verify(&candidate);
+						(secret, valid)
+					})
+					.await
+					.map_err(|_| Error::InvalidSecret("secret verification was cancelled"))?;
+					if !valid {
+						return Ok(None);
+					}
+					secret
+				}
+				None => secret,
+			};
+			if let Secret::Hmac { window, .. } = &secret {
+				if !self.first_use(presented, *window).await? {
+					tracing::debug!(client = identity.name.as_str(), "replayed hmac value");
+					return Ok(None);
+				}
+			}
+			// the token may have been revoked in the meantime
+			let tokens = self.tokens.read().unwrap();
+			Ok(tokens
+				.iter()
+				.find(|token| {
+					token.live(now) && token.secret == secret && token.identity == identity
+				})
+				.map(|token| {
+					token.used(now);
+					identity
+				}))
+		})
+	}
+}
+
+/// Store caching the lookups of another one (unknown keys included) for the given duration.
+/// Keys are indexed by their SHA-256 digest so that they are not kept in clear in memory
+pub struct CachedStore<K> {
+	store: K,
+	ttl: Duration,
+	cache: Mutex<HashMap<[u8; 32], (Option<TokenIdentity>, Instant)>>,
+}
+
+impl<K: ApiKeyStore> CachedStore<K> {
+	/// Cache the lookups of the store for the given duration
+	pub fn new(store: K, ttl: Duration) -> Self {
+		Self {
+			store,
+			ttl,
+			cache: Mutex::default(),
+		}
+	}
+
+	/// Forget all the cached lookups (eg. after revoking a key)
+	pub fn clear(&self) {
+		self.cache.lock().unwrap().clear();
+	}
+}
+
+impl<K: ApiKeyStore> ApiKeyStore for CachedStore<K> {
+	fn lookup<'a>(
+		&'a self,
+		presented: &'a str,
+	) -> LocalBoxFuture<'a, Result<Option<TokenIdentity>>> {
+		Box::pin(async move {
+			let digest: [u8; 32] = Sha256::digest(presented.as_bytes()).into();
+			if let Some((identity, cached)) = self.cache.lock().unwrap().get(&digest) {
+				if cached.elapsed() < self.ttl {
+					return Ok(identity.clone());
+				}
+			}
+			// errors are not cached
+			let identity = self.store.lookup(presented).await?;
+			let mut cache = self.cache.lock().unwrap();
+			if cache.len() >= CACHE_CAPACITY {
+				cache.retain(|_, (_, cached)| cached.elapsed() < self.ttl);
+			}
+			if cache.len() < CACHE_CAPACITY {
+				cache.insert(digest, (identity.clone(), Instant::now()));
+			}
+			Ok(identity)
+		})
+	}
+}
+
+/// File containing secrets (one per line, `#` for comments) that is re-read when it changes,
+/// like a mounted Kubernetes Secret. A line may start with the name of the client followed by a
+/// space, which is required for argon2 and bcrypt hashes
+#[derive(Debug)]
+pub(crate) struct TokenFile {
+	path: PathBuf,
+	// modification time of the loaded file and time of the last check
+	state: Mutex<(Option<SystemTime>, Instant)>,
+}
+
+impl TokenFile {
+	/// Load the secrets of the file in the set
+	pub(crate) fn load(path: &Path, tokens: &TokenSet) -> Result<Self> {
+		let file = Self {
+			path: path.to_owned(),
+			state: Mutex::new((None, Instant::now())),
+		};
+		let modified = file.read(tokens)?;
+		file.state.lock().unwrap().0 = modified;
+		Ok(file)
+	}
+
+	/// Read the file in the set and return its modification time
+	fn read(&self, tokens: &TokenSet) -> Result<Option<SystemTime>> {
+		// follow symlinks as kubelet swaps the ..data link atomically
+		let modified = fs::metadata(&self.path)
+			.and_then(|meta| meta.modified())
+			.ok();
+		let secrets = fs::read_to_string(&self.path)
+			.map_err(Error::TokenFile)?
+			.lines()
+			.map(str::trim)
+			.filter(|line| !line.is_empty() && !line.starts_with('#'))
+			.map(|line| match line.split_once(char::is_whitespace) {
+				Some((name, secret)) => Ok((secret.trim().parse()?, TokenIdentity::new(name))),
+				None => Ok((line.parse()?, TokenIdentity::default())),
+			})
+			.collect::<Result<Vec<_>>>()?;
+		tokens.merge_file(secrets);
+		Ok(modified)
+	}
+
+	/// Reload the file if it changed since the last check (checks are throttled)
+	pub(crate) fn reload(&self, tokens: &TokenSet) {
+		let mut state = self.state.lock().unwrap();
+		if state.1.elapsed() < FILE_CHECK_INTERVAL {
+			return;
+		}
+		state.1 = Instant::now();
+		let modified = fs::metadata(&self.path)
+			.and_then(|meta| meta.modified())
+			.ok();
+		if modified != state.0 {
+			match self.read(tokens) {
+				Ok(modified) => state.0 = modified,
+				// keep the previous secrets
+				Err(e) => tracing::warn!("Failed to reload {}: {}", self.path.display(), e),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn verify_sha256() {
+		let secret = "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+			.parse::<Secret>()
+			.unwrap();
+		assert_eq!(secret, Secret::sha256("hello"));
+		assert!(secret.verify("hello"));
+		assert!(!secret.verify("hello "));
+		assert!("sha256:2cf2".parse::<Secret>().is_err());
+	}
+
+	#[test]
+	fn verify_hmac() {
+		let secret = Secret::hmac(b"key", Duration::from_secs(60));
+		assert!(secret.verify(&Secret::sign(b"key", SystemTime::now())));
+		assert!(!secret.verify(&Secret::sign(b"other", SystemTime::now())));
+		let stale = SystemTime::now() - Duration::from_secs(120);
+		assert!(!secret.verify(&Secret::sign(b"key", stale)));
+		// a set accepts each value once
+		let tokens = TokenSet::new([secret]);
+		let value = Secret::sign(b"key", SystemTime::now());
+		assert!(tokens.verify(&value));
+		assert!(!tokens.verify(&value));
+	}
+
+	#[test]
+	fn revoke_token() {
+		let tokens = TokenSet::new([Secret::sha256("a"), Secret::sha256("b")]);
+		assert!(tokens.revoke("a"));
+		assert!(!tokens.revoke("a"));
+		assert!(!tokens.verify("a"));
+		assert!(tokens.verify("b"));
+	}
+
+	#[test]
+	fn expired_token() {
+		let tokens = TokenSet::default();
+		let past = SystemTime::now() - Duration::from_secs(1);
+		tokens.add_until(Secret::sha256("a"), TokenIdentity::new("ci"), past);
+		assert!(!tokens.verify("a"));
+		tokens.purge();
+		assert!(tokens.usage().is_empty());
+	}
+
+	#[test]
+	fn record_usage() {
+		let tokens = TokenSet::with_identities([(Secret::sha256("a"), TokenIdentity::new("ci"))]);
+		assert!(tokens.verify("a"));
+		assert!(tokens.verify("a"));
+		let usage = tokens.usage();
+		assert_eq!(usage[0].uses, 2);
+		assert!(usage[0].last_used.is_some());
+	}
+
+	#[test]
+	fn identify_client() {
+		let tokens = TokenSet::with_identities([
+			(
+				Secret::sha256("a"),
+				TokenIdentity::new("ci").scope("deploy"),
+			),
+			(Secret::Plain("b".to_owned()), TokenIdentity::new("backup")),
+		]);
+		assert_eq!(
+			tokens.identify("a").map(|id| id.name),
+			Some("ci".to_owned())
+		);
+		assert_eq!(
+			tokens.identify("b").map(|id| id.name),
+			Some("backup".to_owned())
+		);
+		assert!(tokens.identify("c").is_none());
+	}
+
+	#[test]
+	fn reload_file() {
+		let file = |names: &[&str]| {
+			names
+				.iter()
+				.map(|name| (Secret::sha256(name), TokenIdentity::new(name)))
+				.collect()
+		};
+		let tokens = TokenSet::default();
+		tokens.merge_file(file(&["a", "b"]));
+		tokens.add(Secret::sha256("c"), TokenIdentity::new("c"));
+		assert!(tokens.verify("a"));
+		assert!(tokens.revoke("b"));
+		tokens.merge_file(file(&["b", "d"]));
+		assert!(!tokens.verify("a"));
+		assert!(!tokens.verify("b"));
+		assert!(tokens.verify("c"));
+		assert!(tokens.verify("d"));
+	}
+
+	#[test]
+	fn rotate_client() {
+		let tokens = TokenSet::with_identities([
+			(
+				Secret::sha256("a"),
+				TokenIdentity::new("ci").scope("deploy"),
+			),
+			(Secret::sha256("b"), TokenIdentity::new("backup")),
+		]);
+		tokens.rotate("ci", Secret::sha256("c"), Duration::ZERO);
+		assert!(!tokens.verify("a"));
+		assert!(tokens.verify("b"));
+		let identity = tokens.identify("c").unwrap();
+		assert_eq!(identity.name, "ci");
+		assert!(identity.has_scope("deploy"));
+	}
+
+	#[cfg(feature = "bcrypt")]
+	#[actix_rt::test]
+	async fn lookup_slow_hash() {
+		let hash = bcrypt::hash("secret", 4).unwrap();
+		let tokens = TokenSet::with_identities([
+			(Secret::sha256("a"), TokenIdentity::new("backup")),
+			(Secret::Bcrypt(hash), TokenIdentity::new("ci")),
+		]);
+		let name = |identity: Option<TokenIdentity>| identity.map(|id| id.name);
+		let lookup = tokens.lookup("ci.secret").await.unwrap();
+		assert_eq!(name(lookup), Some("ci".to_owned()));
+		let lookup = tokens.lookup("a").await.unwrap();
+		assert_eq!(name(lookup), Some("backup".to_owned()));
+		// slow hashes are only checked for the named client
+		assert!(tokens.lookup("secret").await.unwrap().is_none());
+		assert!(tokens.lookup("backup.secret").await.unwrap().is_none());
+	}
+}
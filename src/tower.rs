@@ -0,0 +1,104 @@
+use crate::{
+	data::{Claims, Jwt},
+	middleware::reject,
+	result::Error as AuthError,
+};
+
+use ::tower::{Layer, Service};
+use futures_util::future::{ready, Either, Ready};
+use http::{header, Request, Response, StatusCode};
+use std::task::{Context, Poll};
+
+/// Tower layer validating the bearer token of the `Authorization` header with the same Jwt
+/// (keys, claims, caches) as the actix middlewares, for axum or hyper services. The claims
+/// are inserted in the request extensions as `Claims` and the rejected requests are answered
+/// with an empty body and the status and challenge of JwtAuth
+///
+/// ```ignore
+/// let app = Router::new()
+///     .route("/", get(handler))
+///     .layer(JwtLayer::new(auth.jwt().clone()));
+/// ```
+#[derive(Clone)]
+pub struct JwtLayer {
+	jwt: Jwt,
+}
+
+impl JwtLayer {
+	/// Validate the tokens with the given Jwt (clones share their keys)
+	pub fn new(jwt: Jwt) -> Self {
+		Self { jwt }
+	}
+}
+
+impl<S> Layer<S> for JwtLayer {
+	type Service = JwtService<S>;
+
+	fn layer(&self, service: S) -> Self::Service {
+		JwtService {
+			service,
+			jwt: self.jwt.clone(),
+		}
+	}
+}
+
+/// Service validating the tokens before calling the inner service
+#[derive(Clone)]
+pub struct JwtService<S> {
+	service: S,
+	jwt: Jwt,
+}
+
+impl<S> JwtService<S> {
+	/// Validate the token of the request and return its claims
+	fn validate<B>(&self, req: &Request<B>) -> Result<Claims, AuthError> {
+		let token = req
+			.headers()
+			.get(header::AUTHORIZATION)
+			.and_then(|value| value.to_str().ok())
+			.and_then(|value| value.split_once(' '))
+			.filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+			.map(|(_, token)| token.trim())
+			.ok_or(AuthError::NoBearer)?;
+		Ok(Claims(self.jwt.validate_jwt(token)?.claims))
+	}
+}
+
+/// Build the response of a rejected request
+fn rejection<B: Default>(e: &AuthError) -> Response<B> {
+	let mut res = Response::new(B::default());
+	*res.status_mut() =
+		StatusCode::from_u16(reject::status(e).as_u16()).unwrap_or(StatusCode::UNAUTHORIZED);
+	if let Ok(challenge) = header::HeaderValue::from_str(&reject::challenge(e, None)) {
+		res.headers_mut()
+			.insert(header::WWW_AUTHENTICATE, challenge);
+	}
+	res
+}
+
+impl<S, B, ResBody> Service<Request<B>> for JwtService<S>
+where
+	S: Service<Request<B>, Response = Response<ResBody>>,
+	ResBody: Default,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut req: Request<B>) -> Self::Future {
+		match self.validate(&req) {
+			Ok(claims) => {
+				req.extensions_mut().insert(claims);
+				Either::Left(self.service.call(req))
+			}
+			Err(e) => {
+				tracing::info!("Request rejected: {}", e);
+				Either::Right(ready(Ok(rejection(&e))))
+			}
+		}
+	}
+}
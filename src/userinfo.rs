@@ -0,0 +1,137 @@
+use crate::{
+	cache::{Cache, MemoryCache},
+	result::{Error, Result},
+};
+
+use actix_web::http::StatusCode;
+use awc::Client;
+use serde_json::Value;
+use std::{sync::Arc, time::Duration};
+
+// maximum number of subjects cached in memory
+const CAPACITY: usize = 1000;
+
+/// Claims fetched from the OIDC userinfo endpoint with the validated token and merged into the
+/// token claims, for providers keeping the profile data out of the access tokens. The claims
+/// are cached by subject
+pub struct Userinfo {
+	url: String,
+	ttl: Duration,
+	cache: Arc<dyn Cache + Send + Sync>,
+}
+
+impl Userinfo {
+	/// Fetch the claims from the given userinfo endpoint, caching them 5 minutes
+	pub fn new(url: &str) -> Self {
+		Self {
+			url: url.to_owned(),
+			ttl: Duration::from_secs(300),
+			cache: Arc::new(MemoryCache::new(CAPACITY)),
+		}
+	}
+
+	/// Keep the claims in the given cache (eg. shared by the replicas) instead of in memory
+	pub fn cache<C>(mut self, cache: C) -> Self
+	where
+		C: Cache + Send + Sync + 'static,
+	{
+		self.cache = Arc::new(cache);
+		self
+	}
+
+	/// Keep the claims of a subject for the given duration
+	pub fn ttl(mut self, ttl: Duration) -> Self {
+		self.ttl = ttl;
+		self
+	}
+
+	/// Return the cached claims of the subject. Cache errors are ignored, the claims being
+	/// fetched again
+	async fn cached(&self, key: &str) -> Option<Value> {
+		match self.cache.get(key).await {
+			Ok(cached) => serde_json::from_slice(&cached?).ok(),
+			Err(e) => {
+				tracing::warn!("{}", e);
+				None
+			}
+		}
+	}
+
+	/// Cache the claims of the subject
+	async fn insert(&self, key: &str, claims: &Value) {
+		let value = claims.to_string().into_bytes();
+		if let Err(e) = self.cache.set(key, value, self.ttl).await {
+			tracing::warn!("{}", e);
+		}
+	}
+
+	/// Fetch the claims of the subject with its token
+	async fn fetch(&self, sub: &str, token: &str) -> Result<Value> {
+		let mut res = Client::default()
+			.get(&self.url)
+			.bearer_auth(token)
+			.send()
+			.await
+			.map_err(|e| Error::Userinfo(e.to_string()))?;
+		match res.status() {
+			// the token was revoked
+			StatusCode::UNAUTHORIZED => return Err(Error::InvalidToken),
+			status if !status.is_success() => {
+				return Err(Error::Userinfo(format!("{} returned {}", self.url, status)))
+			}
+			_ => (),
+		}
+		let claims = res
+			.json::<Value>()
+			.await
+			.map_err(|e| Error::Userinfo(e.to_string()))?;
+		// OIDC Core 5.3.2: the response must be about the subject of the token
+		if claims.get("sub").and_then(Value::as_str) != Some(sub) {
+			return Err(Error::Userinfo("sub mismatch".to_owned()));
+		}
+		Ok(claims)
+	}
+
+	/// Add the userinfo claims of the token subject to its claims
+	pub(crate) async fn enrich(&self, token: &str, claims: &mut Value) -> Result<()> {
+		let sub = claims
+			.get("sub")
+			.and_then(Value::as_str)
+			.ok_or_else(|| Error::ClaimNotFound("sub".to_owned()))?
+			.to_owned();
+		let key = format!("userinfo:{}", sub);
+		let userinfo = match self.cached(&key).await {
+			Some(userinfo) => userinfo,
+			None => {
+				let userinfo = self.fetch(&sub, token).await?;
+				self.insert(&key, &userinfo).await;
+				userinfo
+			}
+		};
+		merge(claims, &userinfo);
+		Ok(())
+	}
+}
+
+/// Add the userinfo claims missing from the token claims (the signed claims win)
+fn merge(claims: &mut Value, userinfo: &Value) {
+	if let (Some(claims), Some(userinfo)) = (claims.as_object_mut(), userinfo.as_object()) {
+		for (key, value) in userinfo {
+			claims.entry(key.as_str()).or_insert_with(|| value.clone());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn merge_claims() {
+		let mut claims = serde_json::json!({ "sub": "alice", "email": "alice@token" });
+		let userinfo = serde_json::json!({ "email": "alice@userinfo", "name": "Alice" });
+		merge(&mut claims, &userinfo);
+		assert_eq!(claims["email"], "alice@token");
+		assert_eq!(claims["name"], "Alice");
+	}
+}
@@ -0,0 +1,211 @@
+use crate::{
+	config,
+	data::Jwks,
+	keys::KeyStore,
+	pem::pem_key,
+	result::{Error, Result},
+};
+
+use awc::Client;
+use futures_util::future::LocalBoxFuture;
+use jsonwebkey as jwk;
+use serde_json::Value;
+use std::{fs, path::PathBuf};
+
+// token of the pod service account
+const SERVICE_ACCOUNT_TOKEN: &str = "/var/run/secrets/kubernetes.io/serviceaccount/token";
+
+/// How to authenticate to Vault
+#[derive(Clone, Debug)]
+pub enum VaultAuth {
+	/// Vault token
+	Token(String),
+	/// Kubernetes auth method: login with the service account token of the pod
+	Kubernetes {
+		/// Vault role bound to the service account
+		role: String,
+		/// mount path of the auth method (`kubernetes` by default)
+		mount: String,
+		/// file of the service account token
+		jwt: PathBuf,
+	},
+}
+
+/// Where the keys are published in Vault
+#[derive(Clone, Debug)]
+enum Source {
+	// public keys of a transit key (one per version)
+	Transit { mount: String, name: String },
+	// keys of the identity tokens issued by Vault
+	Identity,
+}
+
+/// Keys fetched from Vault, for organisations signing their tokens with Vault instead of
+/// publishing a public JWKS
+#[derive(Clone, Debug)]
+pub struct VaultKeys {
+	addr: String,
+	auth: Option<VaultAuth>,
+	namespace: Option<String>,
+	source: Source,
+}
+
+impl VaultKeys {
+	/// Public keys of the transit key `name` (mounted on `transit`), the kid of each version
+	/// being `<name>:v<version>`
+	pub fn transit(addr: &str, name: &str) -> Self {
+		Self {
+			addr: addr.trim_end_matches('/').to_owned(),
+			auth: None,
+			namespace: None,
+			source: Source::Transit {
+				mount: "transit".to_owned(),
+				name: name.to_owned(),
+			},
+		}
+	}
+
+	/// Keys of the identity tokens issued by Vault
+	pub fn identity(addr: &str) -> Self {
+		Self {
+			source: Source::Identity,
+			..Self::transit(addr, "")
+		}
+	}
+
+	/// Same as transit with the address and token read from `VAULT_ADDR` and `VAULT_TOKEN`
+	pub fn transit_from_env(name: &str) -> Result<Self> {
+		let keys = Self::transit(&config::required_var("VAULT_ADDR")?, name);
+		Ok(match config::var("VAULT_TOKEN")? {
+			Some(token) => keys.token(&token),
+			None => keys,
+		})
+	}
+
+	/// Mount path of the transit secrets engine
+	pub fn mount(mut self, path: &str) -> Self {
+		if let Source::Transit { mount, .. } = &mut self.source {
+			*mount = path.trim_matches('/').to_owned();
+		}
+		self
+	}
+
+	/// Authenticate with a Vault token
+	pub fn token(mut self, token: &str) -> Self {
+		self.auth = Some(VaultAuth::Token(token.to_owned()));
+		self
+	}
+
+	/// Authenticate with the Kubernetes auth method and the given role
+	pub fn kubernetes(mut self, role: &str) -> Self {
+		self.auth = Some(VaultAuth::Kubernetes {
+			role: role.to_owned(),
+			mount: "kubernetes".to_owned(),
+			jwt: PathBuf::from(SERVICE_ACCOUNT_TOKEN),
+		});
+		self
+	}
+
+	/// Authenticate with the given method
+	pub fn auth(mut self, auth: VaultAuth) -> Self {
+		self.auth = Some(auth);
+		self
+	}
+
+	/// Vault enterprise namespace
+	pub fn namespace(mut self, namespace: &str) -> Self {
+		self.namespace = Some(namespace.to_owned());
+		self
+	}
+
+	/// Return a Vault token
+	async fn login(&self, client: &Client) -> Result<Option<String>> {
+		match &self.auth {
+			None => Ok(None),
+			Some(VaultAuth::Token(token)) => Ok(Some(token.clone())),
+			Some(VaultAuth::Kubernetes {
+				role,
+				mount,
+				jwt: path,
+			}) => {
+				let jwt = fs::read_to_string(path)
+					.map_err(|e| vault_error(format!("{}: {}", path.display(), e)))?;
+				let url = format!("{}/v1/auth/{}/login", self.addr, mount);
+				let mut req = client.post(url);
+				if let Some(namespace) = &self.namespace {
+					req = req.insert_header(("X-Vault-Namespace", namespace.as_str()));
+				}
+				let mut res = req
+					.send_json(&serde_json::json!({ "role": role, "jwt": jwt.trim() }))
+					.await
+					.map_err(Error::GetError)?;
+				let body = res.json::<Value>().await.map_err(vault_error)?;
+				body.pointer("/auth/client_token")
+					.and_then(Value::as_str)
+					.map(|token| Some(token.to_owned()))
+					.ok_or_else(|| vault_error("no client token in the login response"))
+			}
+		}
+	}
+
+	/// Read a Vault path
+	async fn get(&self, path: &str) -> Result<Value> {
+		let client = Client::default();
+		let token = self.login(&client).await?;
+		let mut req = client.get(format!("{}/v1/{}", self.addr, path));
+		if let Some(token) = &token {
+			req = req.insert_header(("X-Vault-Token", token.as_str()));
+		}
+		if let Some(namespace) = &self.namespace {
+			req = req.insert_header(("X-Vault-Namespace", namespace.as_str()));
+		}
+		let mut res = req.send().await.map_err(Error::GetError)?;
+		if !res.status().is_success() {
+			return Err(vault_error(format!("{} returned {}", path, res.status())));
+		}
+		res.json::<Value>().await.map_err(vault_error)
+	}
+
+	/// Fetch the current keys
+	async fn fetch(&self) -> Result<Vec<jwk::JsonWebKey>> {
+		match &self.source {
+			Source::Identity => {
+				let jwks = self.get("identity/oidc/.well-known/keys").await?;
+				serde_json::from_value::<Jwks>(jwks)
+					.map(|jwks| jwks.keys)
+					.map_err(Error::DeserError)
+			}
+			Source::Transit { mount, name } => {
+				let body = self.get(&format!("{}/keys/{}", mount, name)).await?;
+				let versions = body
+					.pointer("/data/keys")
+					.and_then(Value::as_object)
+					.ok_or_else(|| vault_error(format!("{} is not an asymmetric key", name)))?;
+				Ok(versions
+					.iter()
+					.filter_map(|(version, key)| {
+						let kid = format!("{}:v{}", name, version);
+						let key = key
+							.get("public_key")
+							.and_then(Value::as_str)
+							.and_then(|pem| pem_key(pem, Some(kid.as_str())));
+						if key.is_none() {
+							tracing::warn!("Skipping the unsupported key {}", kid);
+						}
+						key
+					})
+					.collect())
+			}
+		}
+	}
+}
+
+impl KeyStore for VaultKeys {
+	fn keys(&self) -> LocalBoxFuture<'_, Result<Vec<jwk::JsonWebKey>>> {
+		Box::pin(self.fetch())
+	}
+}
+
+fn vault_error<E: std::fmt::Display>(e: E) -> Error {
+	Error::Refresh(format!("vault: {}", e))
+}
@@ -0,0 +1,183 @@
+use crate::{
+	keys::{KeyPolicy, LoadedKey},
+	pem::{self, base64url},
+	result::{Error, Result},
+};
+
+use awc::Client;
+use rustls_pki_types::{CertificateDer, ServerName, TrustAnchor, UnixTime};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::HashMap,
+	str::from_utf8,
+	sync::{Arc, RwLock},
+	time::{Duration, Instant},
+};
+use webpki::{EndEntityCert, KeyUsage};
+use x509_parser::{certificate::X509Certificate, prelude::FromDer};
+
+// lifetime of the keys fetched from an x5u url
+const X5U_TTL: Duration = Duration::from_secs(3600);
+// maximum number of x5u urls cached
+const X5U_CAPACITY: usize = 100;
+// maximum length of a certificate chain
+const MAX_DEPTH: usize = 8;
+
+/// Check that the chain (leaf first) is valid at the given time, chains to one of the CAs
+/// (basic and name constraints, path length, critical extensions, matching signature
+/// algorithms), that the leaf is a signing certificate of the expected subject, and return its
+/// DER SubjectPublicKeyInfo
+fn verify_chain(
+	chain: &[Vec<u8>],
+	cas: &[TrustAnchor<'static>],
+	subject: &str,
+	now: u64,
+) -> std::result::Result<Vec<u8>, String> {
+	let (leaf, intermediates) = chain.split_first().ok_or("empty certificate chain")?;
+	if intermediates.len() >= MAX_DEPTH {
+		return Err("certificate chain too long".to_owned());
+	}
+	let der = CertificateDer::from(leaf.as_slice());
+	let intermediates = intermediates
+		.iter()
+		.map(|der| CertificateDer::from(der.as_slice()))
+		.collect::<Vec<_>>();
+	let cert = EndEntityCert::try_from(&der).map_err(|e| e.to_string())?;
+	cert.verify_for_usage(
+		webpki::ALL_VERIFICATION_ALGS,
+		cas,
+		&intermediates,
+		UnixTime::since_unix_epoch(Duration::from_secs(now)),
+		KeyUsage::server_auth(),
+		None,
+	)
+	.map_err(|e| e.to_string())?;
+	let name = ServerName::try_from(subject).map_err(|e| e.to_string())?;
+	cert.verify_is_valid_for_subject_name(&name)
+		.map_err(|e| e.to_string())?;
+	// webpki doesn't look at the key usage of the leaf
+	let (_, leaf) = X509Certificate::from_der(leaf).map_err(|_| "invalid certificate")?;
+	match leaf.key_usage() {
+		Ok(Some(usage)) if !usage.value.digital_signature() => {
+			Err("the certificate is not a signing certificate".to_owned())
+		}
+		Ok(_) => Ok(leaf.public_key().raw.to_vec()),
+		Err(_) => Err("invalid key usage".to_owned()),
+	}
+}
+
+/// Keys of the certificates designated by the x5u header of the tokens, checked against pinned
+/// CAs and subjects
+#[derive(Default)]
+pub(crate) struct X5u {
+	// trust anchors of the pinned CAs with the subject their leaf certificates must have
+	pins: Vec<(Vec<TrustAnchor<'static>>, String)>,
+	// leaf key and certificate thumbprint by url
+	keys: RwLock<HashMap<String, (Arc<LoadedKey>, String, Instant)>>,
+}
+
+impl X5u {
+	/// Return true if at least one CA is pinned
+	pub(crate) fn enabled(&self) -> bool {
+		!self.pins.is_empty()
+	}
+
+	/// Return a copy pinning the CAs of a PEM bundle for the leaf certificates of the given
+	/// subject (dns name) too (the cache is not copied)
+	pub(crate) fn pin(&self, pem: &str, subject: &str) -> Result<Self> {
+		let invalid = || Error::Config("invalid CA certificate".to_owned());
+		let cas = pem::certificates(pem)
+			.iter()
+			.map(|der| {
+				webpki::anchor_from_trusted_cert(&CertificateDer::from(der.as_slice()))
+					.map(|anchor| anchor.to_owned())
+					.map_err(|_| invalid())
+			})
+			.collect::<Result<Vec<_>>>()?;
+		if cas.is_empty() {
+			return Err(invalid());
+		}
+		ServerName::try_from(subject)
+			.map_err(|_| Error::Config(format!("invalid x5u subject {}", subject)))?;
+		let mut pins = self.pins.clone();
+		pins.push((cas, subject.to_owned()));
+		Ok(Self {
+			pins,
+			keys: RwLock::default(),
+		})
+	}
+
+	/// Return the key of the certificate of the given url if it was fetched less than an hour
+	/// ago, and if its SHA-256 thumbprint is the given one
+	pub(crate) fn get(&self, url: &str, thumbprint: Option<&str>) -> Option<Arc<LoadedKey>> {
+		let keys = self.keys.read().unwrap();
+		let (key, cert_thumbprint, fetched) = keys.get(url)?;
+		if fetched.elapsed() > X5U_TTL || thumbprint.is_some_and(|t| t != cert_thumbprint) {
+			return None;
+		}
+		Some(key.clone())
+	}
+
+	/// Fetch the certificate chain of the given url, check it, and cache the key of its leaf
+	pub(crate) async fn load(&self, url: &str, policy: &KeyPolicy, now: u64) -> Result<()> {
+		let x5u_error = |e: String| Error::Refresh(format!("{}: {}", url, e));
+		let client = Client::default();
+		let mut response = client.get(url).send().await.map_err(Error::GetError)?;
+		let body = response.body().await.map_err(|_| Error::BodyResponse)?;
+		let chain = pem::certificates(from_utf8(&body).map_err(Error::DecodeError)?);
+		let mut res = Err("no pinned CA".to_owned());
+		for (cas, subject) in &self.pins {
+			res = verify_chain(&chain, cas, subject, now);
+			if res.is_ok() {
+				break;
+			}
+		}
+		let spki = res.map_err(x5u_error)?;
+		let thumbprint = base64url(&Sha256::digest(&chain[0]));
+		let mut jwk =
+			pem::spki(&spki).ok_or_else(|| x5u_error("unsupported public key".to_owned()))?;
+		jwk["kid"] = thumbprint.clone().into();
+		let jwk = serde_json::from_value(jwk).map_err(Error::DeserError)?;
+		let key = LoadedKey::load(policy.filter(vec![jwk]))
+			.into_values()
+			.next()
+			.ok_or_else(|| x5u_error("weak public key".to_owned()))?;
+		let mut keys = self.keys.write().unwrap();
+		if keys.len() >= X5U_CAPACITY && !keys.contains_key(url) {
+			let oldest = keys
+				.iter()
+				.min_by_key(|(_, (_, _, fetched))| *fetched)
+				.map(|(url, _)| url.clone());
+			if let Some(oldest) = oldest {
+				keys.remove(&oldest);
+			}
+		}
+		keys.insert(url.to_owned(), (key, thumbprint, Instant::now()));
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn verify_leaf() {
+		let chain = pem::certificates(include_str!("../tests/fixtures/leaf.pem"));
+		let ca = include_str!("../tests/fixtures/ca.pem");
+		let x5u = X5u::default().pin(ca, "keys.example.com").unwrap();
+		let (cas, subject) = &x5u.pins[0];
+		let (_, leaf) = X509Certificate::from_der(&chain[0]).unwrap();
+		let now = leaf.validity().not_before.timestamp() as u64 + 60;
+		let spki = verify_chain(&chain, cas, subject, now).unwrap();
+		assert_eq!(pem::spki(&spki).unwrap()["kty"], "RSA");
+		let expired = leaf.validity().not_after.timestamp() as u64 + 60;
+		assert!(verify_chain(&chain, cas, subject, expired).is_err());
+		// any certificate under the CA can't sign tokens
+		assert!(verify_chain(&chain, cas, "other.example.com", now).is_err());
+		// chain leading to another CA
+		let x5u = X5u::default().pin(include_str!("../tests/fixtures/leaf.pem"), subject);
+		let (cas, _) = &x5u.unwrap().pins[0];
+		assert!(verify_chain(&chain, cas, subject, now).is_err());
+	}
+}